@@ -0,0 +1,327 @@
+use clap::{Args, Parser, Subcommand};
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
+use std::{env, error, fs};
+use uuid::Uuid;
+use xlsx_manager::{
+    create_zip_at, process_input_folder, FileOverride, MemoryBudget,
+    ProcessOptions, ProcessingSummary,
+};
+
+#[derive(Debug, Parser)]
+#[command(name = "xlsx_manager", version, about)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Start the HTTP server (default when no subcommand is given)
+    Serve,
+    /// Process a folder of Excel and Zip files offline into a single Zip file
+    Process(ProcessArgs),
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct ProcessArgs {
+    /// Folder containing the .xlsx, .xls and .zip files to process
+    #[arg(long)]
+    pub input: PathBuf,
+    /// Path of the Zip file to write the processed files to
+    #[arg(long)]
+    pub output: PathBuf,
+    /// Number of rows to delete from the first sheet of each file
+    #[arg(long, default_value_t = 0)]
+    pub delete_rows: usize,
+    /// Detect the banner rows above the table of each file and delete those
+    /// instead of `--delete-rows`
+    #[arg(long)]
+    pub auto_detect_rows: bool,
+    /// Options for the files matching a name or glob pattern, as
+    /// `PATTERN=ROWS` or `PATTERN=auto`. May be repeated; the first match
+    /// applies.
+    #[arg(
+        long = "override",
+        value_name = "PATTERN=ROWS",
+        value_parser = parse_override
+    )]
+    pub overrides: Vec<FileOverride>,
+    /// Bytes the files being processed may use at once, 0 for no limit
+    #[arg(long, value_name = "BYTES", default_value_t = 0)]
+    pub memory_budget: u64,
+}
+
+// Parses a `--override` value.
+fn parse_override(value: &str) -> Result<FileOverride, String> {
+    let (pattern, rows) = value
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected PATTERN=ROWS, got {:?}", value))?;
+    let mut file_override =
+        FileOverride::new(pattern).map_err(|err| err.to_string())?;
+    if rows == "auto" {
+        file_override.detect_header_rows = Some(true);
+    } else {
+        file_override.delete_first_n_rows = Some(
+            rows.parse()
+                .map_err(|_| format!("invalid number of rows {:?}", rows))?,
+        );
+    }
+    Ok(file_override)
+}
+
+/// Runs the `process` subcommand and returns the exit code: `0` when every
+/// file was processed, `1` if anything failed.
+pub(crate) fn run_process(args: &ProcessArgs) -> i32 {
+    match process_folder(args) {
+        Ok(summary) if summary.failed.is_empty() => {
+            for (file, rows) in &summary.rows_deleted {
+                info!("Deleted {} rows from {}", rows, file);
+            }
+            info!(
+                "Processed {} files into {}",
+                summary.processed.len(),
+                args.output.display()
+            );
+            0
+        }
+        Ok(summary) => {
+            for failure in &summary.failed {
+                error!("Failed to process {}: {}", failure.file, failure.error);
+            }
+            error!(
+                "{} of {} files failed",
+                summary.failed.len(),
+                summary.failed.len() + summary.processed.len()
+            );
+            1
+        }
+        Err(err) => {
+            error!("{}", err);
+            1
+        }
+    }
+}
+
+// Processes the input folder inside a temporary staging directory, so the
+// input is never modified, and removes the staging directory afterwards.
+fn process_folder(
+    args: &ProcessArgs,
+) -> Result<ProcessingSummary, Box<dyn error::Error>> {
+    if !args.input.is_dir() {
+        return Err(format!(
+            "Input path {} is not a directory",
+            args.input.display()
+        )
+        .into());
+    }
+
+    let staging_dir =
+        env::temp_dir().join(format!("xlsx_manager-{}", Uuid::new_v4()));
+    let result = process_in_staging_dir(args, &staging_dir);
+
+    if let Err(err) = fs::remove_dir_all(&staging_dir) {
+        warn!(
+            "Failed to remove staging directory {}: {}",
+            staging_dir.display(),
+            err
+        );
+    }
+
+    result
+}
+
+fn process_in_staging_dir(
+    args: &ProcessArgs,
+    staging_dir: &Path,
+) -> Result<ProcessingSummary, Box<dyn error::Error>> {
+    let extracted_dir = staging_dir.join("extracted");
+    let output_dir = staging_dir.join("output");
+    fs::create_dir_all(&extracted_dir)?;
+    fs::create_dir_all(&output_dir)?;
+
+    let options = ProcessOptions {
+        delete_first_n_rows: args.delete_rows,
+        detect_header_rows: args.auto_detect_rows,
+        overrides: args.overrides.clone(),
+        memory_budget: MemoryBudget::new(args.memory_budget),
+        ..Default::default()
+    };
+    let summary = process_input_folder(
+        &args.input,
+        &extracted_dir,
+        &output_dir,
+        &options,
+    )?;
+
+    create_zip_at(&output_dir, &args.output)?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn temp_dir() -> PathBuf {
+        let dir = env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Writes the smallest workbook calamine reads: one sheet with a banner
+    // row above its header.
+    fn write_workbook(path: &Path) {
+        let parts = [
+            (
+                "xl/workbook.xml",
+                "<workbook><sheets><sheet name=\"Sheet1\" r:id=\"rId1\"/>\
+                 </sheets></workbook>",
+            ),
+            (
+                "xl/_rels/workbook.xml.rels",
+                "<Relationships><Relationship Id=\"rId1\" \
+                 Target=\"worksheets/sheet1.xml\"/></Relationships>",
+            ),
+            (
+                "xl/worksheets/sheet1.xml",
+                "<worksheet><sheetData>\
+                 <row r=\"1\"><c r=\"A1\" t=\"inlineStr\"><is><t>Report</t>\
+                 </is></c></row>\
+                 <row r=\"2\"><c r=\"A2\" t=\"inlineStr\"><is><t>Name</t>\
+                 </is></c></row></sheetData></worksheet>",
+            ),
+        ];
+        let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        for (name, content) in parts {
+            let options: zip::write::FileOptions<'_, ()> = Default::default();
+            zip.start_file(name, options).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn process_args(input: &Path, output: &Path) -> ProcessArgs {
+        ProcessArgs {
+            input: input.to_path_buf(),
+            output: output.to_path_buf(),
+            delete_rows: 1,
+            auto_detect_rows: false,
+            overrides: Vec::new(),
+            memory_budget: 0,
+        }
+    }
+
+    #[test]
+    fn overrides_parse_rows_or_auto() {
+        let rows = parse_override("sales_*.xlsx=3").unwrap();
+        assert!(rows.matches("sales_march.xlsx"));
+        assert!(!rows.matches("summary.xlsx"));
+        assert_eq!(rows.delete_first_n_rows, Some(3));
+        assert_eq!(rows.detect_header_rows, None);
+
+        let auto = parse_override("summary.xlsx=auto").unwrap();
+        assert!(auto.matches("summary.xlsx"));
+        assert_eq!(auto.delete_first_n_rows, None);
+        assert_eq!(auto.detect_header_rows, Some(true));
+
+        // The last `=` separates the rows, so patterns may contain one
+        let equals = parse_override("a=b.xlsx=2").unwrap();
+        assert!(equals.matches("a=b.xlsx"));
+        assert_eq!(equals.delete_first_n_rows, Some(2));
+    }
+
+    #[test]
+    fn malformed_overrides_are_rejected() {
+        for value in
+            ["sales.xlsx", "sales.xlsx=", "sales.xlsx=-1", "a=x", "[=2"]
+        {
+            assert!(parse_override(value).is_err(), "{}", value);
+        }
+        let err = Cli::try_parse_from([
+            "xlsx_manager",
+            "process",
+            "--input",
+            "in",
+            "--output",
+            "out.zip",
+            "--override",
+            "sales.xlsx",
+        ])
+        .unwrap_err();
+        assert!(err.to_string().contains("expected PATTERN=ROWS"));
+    }
+
+    #[test]
+    fn process_arguments_are_parsed() {
+        let cli = Cli::try_parse_from([
+            "xlsx_manager",
+            "process",
+            "--input",
+            "in",
+            "--output",
+            "out.zip",
+            "--memory-budget",
+            "1048576",
+            "--override",
+            "a.xlsx=2",
+            "--override",
+            "b.xlsx=auto",
+        ])
+        .unwrap();
+        let args = match cli.command {
+            Some(Command::Process(args)) => args,
+            command => panic!("unexpected command {:?}", command),
+        };
+        assert_eq!(args.input, Path::new("in"));
+        assert_eq!(args.output, Path::new("out.zip"));
+        assert_eq!(args.delete_rows, 0);
+        assert_eq!(args.memory_budget, 1048576);
+        assert_eq!(args.overrides.len(), 2);
+
+        let without_budget =
+            ["xlsx_manager", "process", "--input", "in", "--output", "out.zip"];
+        match Cli::try_parse_from(without_budget).unwrap().command {
+            Some(Command::Process(args)) => assert_eq!(args.memory_budget, 0),
+            command => panic!("unexpected command {:?}", command),
+        }
+        let mut negative = without_budget.to_vec();
+        negative.extend(["--memory-budget", "-1"]);
+        assert!(Cli::try_parse_from(negative).is_err());
+        assert!(Cli::try_parse_from(["xlsx_manager"])
+            .unwrap()
+            .command
+            .is_none());
+    }
+
+    #[test]
+    fn exit_code_reports_failed_files() {
+        let dir = temp_dir();
+        let input = dir.join("input");
+        fs::create_dir(&input).unwrap();
+        write_workbook(&input.join("good.xlsx"));
+        let output = dir.join("out.zip");
+
+        assert_eq!(run_process(&process_args(&input, &output)), 0);
+        assert!(output.is_file());
+
+        // One unreadable workbook fails the run, though the other is written
+        fs::write(input.join("bad.xlsx"), b"not a workbook").unwrap();
+        fs::remove_file(&output).unwrap();
+        assert_eq!(run_process(&process_args(&input, &output)), 1);
+        assert!(output.is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn exit_code_reports_unusable_inputs() {
+        let dir = temp_dir();
+        let output = dir.join("out.zip");
+        // Missing input folder
+        assert_eq!(run_process(&process_args(&dir.join("none"), &output)), 1);
+        // No Excel files
+        assert_eq!(run_process(&process_args(&dir, &output)), 1);
+        assert!(!output.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,12 @@
-use config::{Config, ConfigBuilder, Environment, File};
+use actix_web::http::header::HeaderName;
+use actix_web::http::{Method, Uri};
+use config::{Config, ConfigBuilder, ConfigError, Environment, File};
 use log::info;
 use serde::Deserialize;
 use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use xlsx_manager::ZipLimits;
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct ServerConfig {
@@ -11,19 +16,349 @@ pub(crate) struct ServerConfig {
     pub shutdown_timeout: u64,
 }
 
-// #[derive(Debug, Deserialize)]
-// struct CorsConfig {
-//     allowed_origin: String,
-//     allowed_methods: Vec<String>,
-//     allowed_headers: Vec<String>,
-//     supports_credentials: bool,
-//     max_age: u32,
-// }
+/// Entry of a CORS list allowing any value
+const CORS_ANY: &str = "*";
+
+/// Cross-origin requests accepted by the API.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct CorsConfig {
+    /// Origins such as `https://app.example.com`. `["*"]` allows any origin
+    /// and is only accepted by debug builds.
+    pub allowed_origins: Vec<String>,
+    /// Methods, or `["*"]` for any
+    pub allowed_methods: Vec<String>,
+    /// Request headers, or `["*"]` for any
+    pub allowed_headers: Vec<String>,
+    /// Allow cookies and `Authorization` headers on cross-origin requests
+    pub supports_credentials: bool,
+    /// Seconds browsers may cache a preflight response
+    pub max_age: Option<usize>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "DELETE"]
+                .map(String::from)
+                .to_vec(),
+            allowed_headers: ["Content-Type", "Authorization", "X-API-Key"]
+                .map(String::from)
+                .to_vec(),
+            supports_credentials: false,
+            max_age: Some(3600),
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Checks that every origin, method and header is well-formed and that
+    /// any-origin is only used by debug builds, without credentials.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.allowed_origins.iter().any(|origin| origin == CORS_ANY) {
+            if self.allowed_origins.len() > 1 {
+                return Err("cors.allowed_origins: \"*\" must be the only \
+                            origin"
+                    .to_string());
+            }
+            if !cfg!(debug_assertions) {
+                return Err("cors.allowed_origins: \"*\" is only allowed in \
+                            development builds"
+                    .to_string());
+            }
+            if self.supports_credentials {
+                return Err("cors: supports_credentials cannot be used with \
+                            any origin"
+                    .to_string());
+            }
+        } else {
+            for origin in &self.allowed_origins {
+                if !is_valid_origin(origin) {
+                    return Err(format!(
+                        "cors.allowed_origins: invalid origin {:?}, expected \
+                         scheme://host[:port]",
+                        origin
+                    ));
+                }
+            }
+        }
+
+        if !is_any(&self.allowed_methods) {
+            for method in &self.allowed_methods {
+                if Method::from_bytes(method.as_bytes()).is_err() {
+                    return Err(format!(
+                        "cors.allowed_methods: invalid method {:?}",
+                        method
+                    ));
+                }
+            }
+        }
+        if !is_any(&self.allowed_headers) {
+            for header in &self.allowed_headers {
+                if HeaderName::from_bytes(header.as_bytes()).is_err() {
+                    return Err(format!(
+                        "cors.allowed_headers: invalid header {:?}",
+                        header
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks whether a CORS list is `["*"]`.
+pub(crate) fn is_any(values: &[String]) -> bool {
+    matches!(values, [value] if value == CORS_ANY)
+}
+
+// An origin is an http(s) scheme and a host with an optional port, without
+// user info, path or query. Wildcards are not origins.
+fn is_valid_origin(origin: &str) -> bool {
+    let uri = match origin.parse::<Uri>() {
+        Ok(uri) => uri,
+        Err(_) => return false,
+    };
+    let (scheme, authority) = match (uri.scheme_str(), uri.authority()) {
+        (Some(scheme @ ("http" | "https")), Some(authority)) => {
+            (scheme, authority)
+        }
+        _ => return false,
+    };
+    let host = authority.host();
+    let valid_host = (host.starts_with('[') && host.ends_with(']'))
+        || (!host.is_empty()
+            && host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.'));
+    // Rules out user info and empty or out of range ports
+    let expected_authority = match authority.port_u16() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    };
+    valid_host
+        && authority.as_str() == expected_authority
+        && origin == format!("{}://{}", scheme, authority)
+}
+
+/// Limits applied to files received by `/upload`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct UploadConfig {
+    /// Largest accepted size of a single uploaded file, in bytes
+    pub max_file_size: u64,
+    /// Largest accepted total size of all files of one request, in bytes
+    pub max_request_size: u64,
+    /// Largest total size of the files extracted from one Zip file, in
+    /// bytes, `0` for no limit
+    pub max_extracted_size: u64,
+    /// Most entries one Zip file may hold, `0` for no limit
+    pub max_zip_entries: usize,
+}
+
+impl UploadConfig {
+    /// Limits applied when the uploaded Zip files are extracted.
+    pub(crate) fn zip_limits(&self) -> ZipLimits {
+        ZipLimits {
+            max_extracted_size: self.max_extracted_size,
+            max_entries: self.max_zip_entries,
+        }
+    }
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        UploadConfig {
+            max_file_size: 100 * 1024 * 1024,
+            max_request_size: 500 * 1024 * 1024,
+            max_extracted_size: 1024 * 1024 * 1024,
+            max_zip_entries: 10_000,
+        }
+    }
+}
+
+/// Expiry of job data left in the upload folder.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct JanitorConfig {
+    pub enabled: bool,
+    /// Seconds between two sweeps
+    pub interval: u64,
+    /// Seconds uploaded files are kept after their last change
+    pub upload_ttl: u64,
+    /// Seconds processed results are kept after their last change
+    pub result_ttl: u64,
+    /// Bytes the upload folder may use before the oldest jobs are evicted,
+    /// `0` for no limit
+    pub max_disk_usage: u64,
+}
+
+impl Default for JanitorConfig {
+    fn default() -> Self {
+        JanitorConfig {
+            enabled: true,
+            interval: 300,
+            upload_ttl: 24 * 60 * 60,
+            result_ttl: 24 * 60 * 60,
+            max_disk_usage: 0,
+        }
+    }
+}
+
+/// Storage of the processing profiles saved through `/profiles`.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct ProfilesConfig {
+    /// Folder holding one `{name}.toml` file per profile
+    pub dir: String,
+}
+
+impl Default for ProfilesConfig {
+    fn default() -> Self {
+        ProfilesConfig { dir: "profiles".to_string() }
+    }
+}
+
+/// API keys accepted by the API. Each key belongs to a principal, who owns
+/// the jobs uploaded with it.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct AuthConfig {
+    pub enabled: bool,
+    pub keys: Vec<ApiKeyConfig>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ApiKeyConfig {
+    pub principal: String,
+    pub key: String,
+}
+
+// Keeps the keys out of the logged configuration.
+impl fmt::Debug for ApiKeyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiKeyConfig")
+            .field("principal", &self.principal)
+            .field("key", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Request rate and job concurrency limits.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct LimitsConfig {
+    /// `/upload` and `/process` requests each client may make per minute,
+    /// `0` for no limit
+    pub requests_per_minute: u32,
+    /// Requests a client may make in a row before the rate applies
+    pub burst: u32,
+    /// Jobs processed at the same time, `0` for no limit
+    pub max_running_jobs: usize,
+    /// `/process` requests waiting for a free slot before new ones are
+    /// turned away
+    pub max_queued_jobs: usize,
+    /// Seconds a `/process` request waits for a free slot
+    pub queue_timeout: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            requests_per_minute: 60,
+            burst: 10,
+            max_running_jobs: 4,
+            max_queued_jobs: 16,
+            queue_timeout: 30,
+        }
+    }
+}
+
+/// Thresholds below which `GET /readyz` reports the server as not ready.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct HealthConfig {
+    /// Bytes that must be free on the disk of the upload folder
+    pub min_free_disk_space: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig { min_free_disk_space: 1024 * 1024 * 1024 }
+    }
+}
+
+/// How log lines are written to stderr.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogFormat {
+    /// One human-readable line per message
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct LoggingConfig {
+    pub format: LogFormat,
+}
+
+/// Thread pool processing the Excel files, apart from the HTTP workers.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct ProcessingConfig {
+    /// Threads shared by the running jobs, `0` for one per CPU
+    pub threads: usize,
+    /// Bytes the files being processed may use at once, `0` for no limit.
+    /// Files wait for memory to free up before they start.
+    pub memory_budget: u64,
+}
+
+/// HTTPS settings. When present, `server.port` only accepts HTTPS.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct TlsConfig {
+    /// PEM file holding the certificate chain, leaf certificate first
+    pub cert: PathBuf,
+    /// PEM file holding the private key
+    pub key: PathBuf,
+    /// Port of a plain-HTTP listener redirecting every request to HTTPS
+    #[serde(default)]
+    pub redirect_port: Option<u16>,
+    /// Seconds between two checks of the files for a renewed certificate
+    #[serde(default = "default_reload_interval")]
+    pub reload_interval: u64,
+}
+
+fn default_reload_interval() -> u64 {
+    60
+}
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct AppConfig {
     pub server: ServerConfig,
-    // cors: CorsConfig,
+    #[serde(default)]
+    pub upload: UploadConfig,
+    #[serde(default)]
+    pub janitor: JanitorConfig,
+    #[serde(default)]
+    pub profiles: ProfilesConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub cors: CorsConfig,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub processing: ProcessingConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
 }
 
 impl AppConfig {
@@ -42,6 +377,116 @@ impl AppConfig {
             .add_source(Environment::with_prefix("APP").separator("__"));
 
         // Try to build and deserialize into the AppConfig struct
-        builder.build()?.try_deserialize()
+        let config: AppConfig = builder.build()?.try_deserialize()?;
+        config.cors.validate().map_err(ConfigError::Message)?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors(origins: &[&str]) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.iter().map(|o| o.to_string()).collect(),
+            ..CorsConfig::default()
+        }
+    }
+
+    #[test]
+    fn default_cors_is_valid() {
+        assert_eq!(CorsConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn any_origin_is_only_allowed_in_debug_builds() {
+        let result = cors(&["*"]).validate();
+        if cfg!(debug_assertions) {
+            assert_eq!(result, Ok(()));
+        } else {
+            assert!(result.unwrap_err().contains("development builds"));
+        }
+    }
+
+    #[test]
+    fn any_origin_cannot_be_used_with_credentials() {
+        let config = CorsConfig { supports_credentials: true, ..cors(&["*"]) };
+        let err = config.validate().unwrap_err();
+        if cfg!(debug_assertions) {
+            assert!(err.contains("supports_credentials"));
+        } else {
+            assert!(err.contains("development builds"));
+        }
+    }
+
+    #[test]
+    fn any_origin_must_be_the_only_origin() {
+        for origins in
+            [&["*", "https://a.example.com"], &["https://a.example.com", "*"]]
+        {
+            let err = cors(origins).validate().unwrap_err();
+            assert!(err.contains("must be the only origin"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn explicit_origins_are_accepted() {
+        let config = CorsConfig {
+            supports_credentials: true,
+            ..cors(&[
+                "https://app.example.com",
+                "http://localhost:3000",
+                "http://127.0.0.1:8080",
+                "https://[::1]:8443",
+            ])
+        };
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn malformed_origins_are_rejected() {
+        for origin in [
+            "",
+            "app.example.com",
+            "ftp://app.example.com",
+            "https://app.example.com/",
+            "https://app.example.com/path",
+            "https://app.example.com?query",
+            "https://user@app.example.com",
+            "https://app.example.com:99999",
+            "https://app.example.com:",
+            "https://[::1]:",
+            "https://",
+            "*.example.com",
+            "https://*.example.com",
+        ] {
+            let err = cors(&["https://ok.example.com", origin])
+                .validate()
+                .expect_err(origin);
+            assert!(err.contains("invalid origin"), "{}: {}", origin, err);
+        }
+    }
+
+    #[test]
+    fn methods_and_headers_are_checked() {
+        let any = CorsConfig {
+            allowed_methods: vec!["*".to_string()],
+            allowed_headers: vec!["*".to_string()],
+            ..CorsConfig::default()
+        };
+        assert_eq!(any.validate(), Ok(()));
+
+        let method = CorsConfig {
+            allowed_methods: vec!["GET".to_string(), "BAD METHOD".to_string()],
+            ..CorsConfig::default()
+        };
+        assert!(method.validate().unwrap_err().contains("invalid method"));
+
+        let header = CorsConfig {
+            allowed_headers: vec!["X-Ok".to_string(), "Bad:Header".to_string()],
+            ..CorsConfig::default()
+        };
+        assert!(header.validate().unwrap_err().contains("invalid header"));
     }
 }
@@ -0,0 +1,152 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Errors returned by the processing engine.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Unable to read workbook {path:?}: {source}")]
+    UnreadableWorkbook {
+        path: PathBuf,
+        #[source]
+        source: calamine::Error,
+    },
+    #[error("Sheet {index} not found in workbook {path:?}")]
+    MissingSheet { path: PathBuf, index: usize },
+    #[error("Invalid Excel serial date {serial} at row {row}, column {col}")]
+    InvalidDate { serial: f64, row: u32, col: u16 },
+    #[error("Unable to write workbook {path:?}: {source}")]
+    WorkbookWrite {
+        path: PathBuf,
+        #[source]
+        source: xlsxwriter::XlsxError,
+    },
+    #[error("Unable to read zip file {path:?}: {source}")]
+    ZipRead {
+        path: PathBuf,
+        #[source]
+        source: zip::result::ZipError,
+    },
+    #[error("Unable to write zip file {path:?}: {source}")]
+    ZipWrite {
+        path: PathBuf,
+        #[source]
+        source: zip::result::ZipError,
+    },
+    #[error("No Excel files (.xlsx or .xls) found in {0:?}")]
+    NoExcelFiles(PathBuf),
+    #[error("Invalid file path: {0}")]
+    InvalidFilePath(String),
+    #[error("Invalid file name pattern {pattern:?}: {source}")]
+    InvalidPattern {
+        pattern: String,
+        #[source]
+        source: glob::PatternError,
+    },
+    #[error("{what} limit exceeded: {actual} > {limit}")]
+    LimitExceeded { what: String, limit: u64, actual: u64 },
+    #[error("Output {output:?} of {file:?} is already written by {other:?}")]
+    DuplicateOutput { file: String, other: String, output: String },
+    #[error("Processing was cancelled")]
+    Cancelled,
+    #[error("IO error on {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl Error {
+    /// Stable, machine-readable identifier of the error kind.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::UnreadableWorkbook { .. } => "unreadable_workbook",
+            Error::MissingSheet { .. } => "missing_sheet",
+            Error::InvalidDate { .. } => "invalid_date",
+            Error::WorkbookWrite { .. } => "workbook_write_failed",
+            Error::ZipRead { .. } => "invalid_zip",
+            Error::ZipWrite { .. } => "zip_write_failed",
+            Error::NoExcelFiles(_) => "no_excel_files",
+            Error::InvalidFilePath(_) => "invalid_file_path",
+            Error::InvalidPattern { .. } => "invalid_pattern",
+            Error::LimitExceeded { .. } => "limit_exceeded",
+            Error::DuplicateOutput { .. } => "duplicate_output",
+            Error::Cancelled => "cancelled",
+            Error::Io { .. } => "io_error",
+        }
+    }
+
+    /// Path of the file or folder the error is about, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::UnreadableWorkbook { path, .. }
+            | Error::MissingSheet { path, .. }
+            | Error::WorkbookWrite { path, .. }
+            | Error::ZipRead { path, .. }
+            | Error::ZipWrite { path, .. }
+            | Error::NoExcelFiles(path)
+            | Error::Io { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The error message with its path relative to `base`, or reduced to
+    /// its file name when outside `base`, so that it does not reveal where
+    /// the files are stored.
+    pub fn message_relative_to(&self, base: &Path) -> String {
+        let message = self.to_string();
+        let path = match self.path() {
+            Some(path) => path,
+            None => return message,
+        };
+        let shown = path
+            .strip_prefix(base)
+            .ok()
+            .filter(|relative| {
+                relative.is_relative() && !relative.as_os_str().is_empty()
+            })
+            .or_else(|| path.file_name().map(Path::new))
+            .unwrap_or(Path::new(""));
+        message.replace(&format!("{:?}", path), &format!("{:?}", shown))
+    }
+
+    /// Returns a closure wrapping an I/O error with the path it occurred on,
+    /// for use with `map_err`.
+    pub(crate) fn io(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+        move |source| Error::Io { path: path.to_path_buf(), source }
+    }
+}
+
+/// Result type used throughout the processing engine.
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_hide_the_folders_around_the_base() {
+        let base = Path::new("/srv/upload/job");
+        let error = Error::MissingSheet {
+            path: base.join("inputs").join("a.xlsx"),
+            index: 0,
+        };
+        assert_eq!(
+            error.message_relative_to(base),
+            "Sheet 0 not found in workbook \"inputs/a.xlsx\""
+        );
+        assert_eq!(
+            error.message_relative_to(Path::new("/elsewhere")),
+            "Sheet 0 not found in workbook \"a.xlsx\""
+        );
+        assert!(error.to_string().contains("/srv/upload/job/inputs/a.xlsx"));
+
+        let error = Error::LimitExceeded {
+            what: "Zip entries".into(),
+            limit: 1,
+            actual: 2,
+        };
+        assert_eq!(error.message_relative_to(base), error.to_string());
+    }
+}
@@ -0,0 +1,411 @@
+use crate::config::JanitorConfig;
+use crate::jobs::{is_legacy_result, JobStore, INPUTS_DIR, JOB_FILE, RUNS_DIR};
+use crate::progress::{ProgressHub, Reservation};
+use actix_web::rt::time;
+use actix_web::web;
+use log::{debug, error, info};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use walkdir::WalkDir;
+
+/// Periodically removes expired job data from the upload folder. Runs until
+/// the server stops.
+pub(crate) async fn run(
+    config: JanitorConfig,
+    store: web::Data<JobStore>,
+    progress: web::Data<ProgressHub>,
+) {
+    if !config.enabled {
+        info!("Janitor disabled");
+        return;
+    }
+    info!("Janitor started: {:?}", config);
+
+    let mut interval =
+        time::interval(Duration::from_secs(config.interval.max(1)));
+    loop {
+        interval.tick().await;
+
+        let sweep_config = config.clone();
+        let store = store.clone();
+        let progress = progress.clone();
+        match web::block(move || sweep(&store, &progress, &sweep_config)).await
+        {
+            Ok(Ok(0)) => debug!("Janitor sweep: nothing to reclaim"),
+            Ok(Ok(reclaimed)) => {
+                info!("Janitor sweep reclaimed {} bytes", reclaimed)
+            }
+            Ok(Err(err)) => error!("Janitor sweep failed: {}", err),
+            Err(err) => error!("Janitor sweep could not run: {}", err),
+        }
+    }
+}
+
+// Disk usage and age of one job folder or one entry inside it.
+struct Usage {
+    path: PathBuf,
+    size: u64,
+    last_modified: SystemTime,
+}
+
+/// Runs a single sweep over the upload folder and returns the number of bytes
+/// reclaimed. Jobs being processed are left alone, and jobs being cleaned
+/// up cannot start a run until the janitor is done with them.
+pub(crate) fn sweep(
+    store: &JobStore,
+    progress: &ProgressHub,
+    config: &JanitorConfig,
+) -> io::Result<u64> {
+    let upload_dir = store.root();
+    if !upload_dir.is_dir() {
+        return Ok(0);
+    }
+
+    let now = SystemTime::now();
+    let upload_ttl = Duration::from_secs(config.upload_ttl);
+    let result_ttl = Duration::from_secs(config.result_ttl);
+    let mut reclaimed = 0;
+
+    // Expire raw uploads and results separately, so results can outlive the
+    // files they were produced from
+    for job in fs::read_dir(upload_dir)? {
+        let job_path = job?.path();
+        if !job_path.is_dir() {
+            continue;
+        }
+        let _reservation = match reserve_idle(&job_path, progress) {
+            Some(reservation) => reservation,
+            None => continue,
+        };
+
+        let mut removed_any = false;
+        let mut removed_runs = Vec::new();
+        for (path, is_result) in expirable_entries(&job_path)? {
+            let usage = usage(&path);
+            let ttl = if is_result { result_ttl } else { upload_ttl };
+            if is_expired(&usage, now, ttl) {
+                reclaimed += remove(&usage);
+                removed_any = true;
+                if let Some(version) = run_version(&path) {
+                    removed_runs.push(version);
+                }
+            }
+        }
+
+        // Keep the metadata from listing runs whose folder is gone
+        if !removed_runs.is_empty() {
+            if let Some(job_id) = job_id(&job_path) {
+                if let Err(err) = store
+                    .update(job_id, |record| record.forget_runs(&removed_runs))
+                {
+                    error!(
+                        "Failed to update metadata of job {}: {}",
+                        job_id, err
+                    );
+                }
+            }
+        }
+
+        // Drop the job folder once nothing but its metadata is left in it. A
+        // folder that was already empty may belong to an upload that just
+        // started.
+        let is_empty = expirable_entries(&job_path)?.is_empty();
+        if is_empty
+            && (removed_any || is_expired(&usage(&job_path), now, upload_ttl))
+        {
+            info!("Removing empty job folder {}", job_path.display());
+            fs::remove_dir_all(&job_path)?;
+        }
+    }
+
+    // Evict whole jobs, oldest first, while the folder is over its quota.
+    // Jobs being processed count towards it but are not evicted.
+    if config.max_disk_usage > 0 {
+        let mut jobs = Vec::new();
+        let mut total = 0;
+        for path in fs::read_dir(upload_dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_dir())
+        {
+            let job = usage(&path);
+            total += job.size;
+            jobs.push(job);
+        }
+        jobs.sort_by_key(|job| job.last_modified);
+
+        for job in jobs {
+            if total <= config.max_disk_usage {
+                break;
+            }
+            let _reservation = match reserve_idle(&job.path, progress) {
+                Some(reservation) => reservation,
+                None => continue,
+            };
+            info!(
+                "Upload folder uses {} bytes, over the {} bytes quota",
+                total, config.max_disk_usage
+            );
+            let freed = remove(&job);
+            total -= freed;
+            reclaimed += freed;
+        }
+    }
+
+    Ok(reclaimed)
+}
+
+fn job_id(job_path: &Path) -> Option<&str> {
+    job_path.file_name().and_then(|name| name.to_str())
+}
+
+// Marks the job as running so no run can start while its files are
+// removed, or returns `None` if a run of the job is in progress. The upload
+// folder belongs to this server: the runs a previous server left
+// `processing` in `job.json` are failed when it starts, so only the runs
+// tracked by `progress` are still going.
+fn reserve_idle<'a>(
+    job_path: &Path,
+    progress: &'a ProgressHub,
+) -> Option<Reservation<'a>> {
+    progress.reserve(job_id(job_path)?)
+}
+
+// Version of the run whose folder is at `path`, if it is one.
+fn run_version(path: &Path) -> Option<u32> {
+    let parent = path.parent()?.file_name()?;
+    if parent != RUNS_DIR {
+        return None;
+    }
+    path.file_name()?.to_str()?.parse().ok()
+}
+
+// Lists what the janitor may expire in a job folder, flagging results: each
+// uploaded file in `inputs/`, each run folder in `runs/` and, for jobs
+// created before that layout, the entries of the job folder itself. The job
+// metadata lives as long as the job folder.
+fn expirable_entries(job_path: &Path) -> io::Result<Vec<(PathBuf, bool)>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(job_path)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|name| name.to_str());
+        if name == Some(INPUTS_DIR) || name == Some(RUNS_DIR) {
+            let is_result = name == Some(RUNS_DIR);
+            for child in fs::read_dir(&path)? {
+                entries.push((child?.path(), is_result));
+            }
+        } else if !is_metadata_entry(&path) {
+            entries.push((path.clone(), is_result_entry(&path)));
+        }
+    }
+    Ok(entries)
+}
+
+// Besides the legacy results, entries left in a job folder are uploads
+// that could not be moved to `inputs/`.
+fn is_result_entry(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(is_legacy_result)
+}
+
+// `job.json` and the temporary file it is written through.
+fn is_metadata_entry(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(JOB_FILE))
+}
+
+fn is_expired(usage: &Usage, now: SystemTime, ttl: Duration) -> bool {
+    now.duration_since(usage.last_modified).is_ok_and(|age| age > ttl)
+}
+
+/// Returns the bytes used by the files of a folder.
+pub(crate) fn disk_usage(path: &Path) -> u64 {
+    usage(path).size
+}
+
+// Measures the total size and most recent modification of a file or folder.
+fn usage(path: &Path) -> Usage {
+    let mut size = 0;
+    let mut last_modified = SystemTime::UNIX_EPOCH;
+    for metadata in WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+    {
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+        if let Ok(modified) = metadata.modified() {
+            last_modified = last_modified.max(modified);
+        }
+    }
+    Usage { path: path.to_path_buf(), size, last_modified }
+}
+
+// Deletes a file or folder and returns the bytes reclaimed.
+fn remove(usage: &Usage) -> u64 {
+    let result = if usage.path.is_dir() {
+        fs::remove_dir_all(&usage.path)
+    } else {
+        fs::remove_file(&usage.path)
+    };
+    match result {
+        Ok(()) => {
+            info!("Removed {} ({} bytes)", usage.path.display(), usage.size);
+            usage.size
+        }
+        Err(err) => {
+            error!("Failed to remove {}: {}", usage.path.display(), err);
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::{JobOptions, JobRecord, JobRun, JobStatus};
+    use std::fs::File;
+    use uuid::Uuid;
+
+    const HOUR: u64 = 60 * 60;
+
+    fn temp_store() -> JobStore {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&root).unwrap();
+        JobStore::new(root)
+    }
+
+    fn config(upload_ttl: u64, result_ttl: u64) -> JanitorConfig {
+        JanitorConfig {
+            upload_ttl,
+            result_ttl,
+            max_disk_usage: 0,
+            ..JanitorConfig::default()
+        }
+    }
+
+    // Creates a job with a 1000 byte upload and the given completed runs,
+    // each holding a 1000 byte output. Returns its folder.
+    fn create_job(store: &JobStore, runs: &[u32]) -> PathBuf {
+        let job_id = Uuid::new_v4().to_string();
+        let job_dir = store.root().join(&job_id);
+        fs::create_dir_all(job_dir.join(INPUTS_DIR)).unwrap();
+        fs::write(job_dir.join(INPUTS_DIR).join("a.xlsx"), [0; 1000]).unwrap();
+
+        let mut record = JobRecord::new(job_id, vec!["a.xlsx".to_string()]);
+        for &version in runs {
+            let run_dir = job_dir.join(RUNS_DIR).join(version.to_string());
+            fs::create_dir_all(&run_dir).unwrap();
+            fs::write(run_dir.join("out.zip"), [0; 1000]).unwrap();
+            let options = JobOptions {
+                num_rows_to_delete: 1,
+                auto_detect_rows: false,
+                overrides: Vec::new(),
+                profile: None,
+                profile_version: None,
+            };
+            let mut run = JobRun::start(version, options);
+            run.status = JobStatus::Completed;
+            record.record_run(run);
+        }
+        store.save(&record).unwrap();
+        job_dir
+    }
+
+    // Makes everything under `path` last modified `age` seconds ago.
+    fn set_age(path: &Path, age: u64) {
+        let modified = SystemTime::now() - Duration::from_secs(age);
+        for entry in WalkDir::new(path).contents_first(true) {
+            let entry = entry.unwrap();
+            File::open(entry.path()).unwrap().set_modified(modified).unwrap();
+        }
+    }
+
+    fn job_id_of(job_dir: &Path) -> &str {
+        job_id(job_dir).unwrap()
+    }
+
+    #[test]
+    fn uploads_and_runs_expire_separately() {
+        let store = temp_store();
+        let progress = ProgressHub::default();
+        let job_dir = create_job(&store, &[1, 2]);
+        let input = job_dir.join(INPUTS_DIR).join("a.xlsx");
+        set_age(&input, 2 * HOUR);
+        set_age(&job_dir.join(RUNS_DIR).join("1"), 2 * HOUR);
+
+        // Uploads are kept for an hour and results for a day
+        let reclaimed = sweep(&store, &progress, &config(HOUR, 24 * HOUR));
+        assert_eq!(reclaimed.unwrap(), 1000);
+        assert!(!input.exists());
+        assert!(job_dir.join(RUNS_DIR).join("1").exists());
+
+        // Expiring a run drops it from the metadata too
+        set_age(&job_dir.join(RUNS_DIR).join("1"), 48 * HOUR);
+        let reclaimed = sweep(&store, &progress, &config(HOUR, 24 * HOUR));
+        assert_eq!(reclaimed.unwrap(), 1000);
+        assert!(!job_dir.join(RUNS_DIR).join("1").exists());
+        assert!(job_dir.join(RUNS_DIR).join("2").exists());
+        let record = store.get(job_id_of(&job_dir)).unwrap().unwrap();
+        let versions: Vec<_> = record.runs.iter().map(|r| r.version).collect();
+        assert_eq!(versions, [2]);
+        assert_eq!(record.status, JobStatus::Completed);
+
+        // The folder goes once nothing but its metadata is left
+        set_age(&job_dir.join(RUNS_DIR).join("2"), 48 * HOUR);
+        sweep(&store, &progress, &config(HOUR, 24 * HOUR)).unwrap();
+        assert!(!job_dir.exists());
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn active_jobs_are_skipped() {
+        let store = temp_store();
+        let progress = ProgressHub::default();
+        let job_dir = create_job(&store, &[1]);
+        set_age(&job_dir, 48 * HOUR);
+
+        let reservation = progress.reserve(job_id_of(&job_dir)).unwrap();
+        let reclaimed = sweep(&store, &progress, &config(HOUR, HOUR));
+        assert_eq!(reclaimed.unwrap(), 0);
+        assert!(job_dir.join(INPUTS_DIR).join("a.xlsx").exists());
+
+        drop(reservation);
+        sweep(&store, &progress, &config(HOUR, HOUR)).unwrap();
+        assert!(!job_dir.exists());
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn quota_evicts_the_oldest_idle_jobs_first() {
+        let store = temp_store();
+        let progress = ProgressHub::default();
+        let oldest = create_job(&store, &[1]);
+        let older = create_job(&store, &[1]);
+        let newer = create_job(&store, &[1]);
+        let newest = create_job(&store, &[1]);
+        set_age(&oldest, 4 * HOUR);
+        set_age(&older, 3 * HOUR);
+        set_age(&newer, 2 * HOUR);
+        set_age(&newest, HOUR);
+        let job_size = disk_usage(&newest);
+
+        // The oldest job is running, so the next ones go in its place until
+        // two jobs fit
+        let _running = progress.reserve(job_id_of(&oldest)).unwrap();
+        let config = JanitorConfig {
+            max_disk_usage: 2 * job_size,
+            ..config(24 * HOUR, 24 * HOUR)
+        };
+        sweep(&store, &progress, &config).unwrap();
+        assert!(oldest.exists());
+        assert!(!older.exists());
+        assert!(!newer.exists());
+        assert!(newest.exists());
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+}
@@ -0,0 +1,654 @@
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use uuid::Uuid;
+use xlsx_manager::{FileOverride, ProcessOptions};
+
+/// Folder holding one sub-folder per job
+pub(crate) const UPLOAD_DIR: &str = "upload";
+/// Name of the metadata file stored in each job folder
+pub(crate) const JOB_FILE: &str = "job.json";
+/// Sub-folder of a job holding the uploaded files, which are never modified
+pub(crate) const INPUTS_DIR: &str = "inputs";
+/// Sub-folder of a job holding one numbered folder per run
+pub(crate) const RUNS_DIR: &str = "runs";
+/// Error of a run stopped by a server shutdown
+pub(crate) const INTERRUPTED_MESSAGE: &str = "Interrupted by a server shutdown";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum JobStatus {
+    Uploaded,
+    Processing,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub(crate) const ALL: [JobStatus; 5] = [
+        JobStatus::Uploaded,
+        JobStatus::Processing,
+        JobStatus::Completed,
+        JobStatus::Failed,
+        JobStatus::Cancelled,
+    ];
+
+    /// Name of the status, as serialized.
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Uploaded => "uploaded",
+            JobStatus::Processing => "processing",
+            JobStatus::Completed => "completed",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Options a run of `/process` was started with. Unknown fields are
+/// rejected, so a profile never silently drops an option it cannot apply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct JobOptions {
+    #[serde(default)]
+    pub num_rows_to_delete: usize,
+    /// Banner rows were detected per file instead
+    #[serde(default)]
+    pub auto_detect_rows: bool,
+    /// Options for specific files, the first matching one applies
+    #[serde(default)]
+    pub overrides: Vec<JobOverride>,
+    /// Profile and profile version the options were taken from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile_version: Option<u32>,
+}
+
+impl JobOptions {
+    /// Converts the options to the engine's, validating the override
+    /// patterns.
+    pub(crate) fn process_options(
+        &self,
+    ) -> xlsx_manager::Result<ProcessOptions> {
+        let overrides = self
+            .overrides
+            .iter()
+            .map(|job_override| {
+                let mut file_override =
+                    FileOverride::new(&job_override.pattern)?;
+                file_override.delete_first_n_rows =
+                    job_override.num_rows_to_delete;
+                file_override.detect_header_rows =
+                    job_override.auto_detect_rows;
+                Ok(file_override)
+            })
+            .collect::<xlsx_manager::Result<_>>()?;
+        Ok(ProcessOptions {
+            delete_first_n_rows: self.num_rows_to_delete,
+            detect_header_rows: self.auto_detect_rows,
+            overrides,
+            ..Default::default()
+        })
+    }
+}
+
+/// Options replacing the job defaults for the files whose name matches
+/// `pattern`, a file name or a glob pattern such as `sales_*.xlsx`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct JobOverride {
+    pub pattern: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_rows_to_delete: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_detect_rows: Option<bool>,
+}
+
+/// A file of the job that could not be processed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct FailedFile {
+    pub file: String,
+    pub code: String,
+    pub message: String,
+}
+
+/// One `/process` run of a job and what it produced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct JobRun {
+    /// Number of the run within its job, starting at 1
+    pub version: u32,
+    pub options: JobOptions,
+    pub status: JobStatus,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub time_taken: Option<String>,
+    pub output_files: Vec<String>,
+    /// Number of rows deleted from each output file
+    #[serde(default)]
+    pub rows_deleted: BTreeMap<String, usize>,
+    pub zip_file_name: Option<String>,
+    pub failed_files: Vec<FailedFile>,
+    pub error: Option<String>,
+}
+
+impl JobRun {
+    pub(crate) fn start(version: u32, options: JobOptions) -> Self {
+        JobRun {
+            version,
+            options,
+            status: JobStatus::Processing,
+            started_at: Utc::now(),
+            finished_at: None,
+            time_taken: None,
+            output_files: Vec::new(),
+            rows_deleted: BTreeMap::new(),
+            zip_file_name: None,
+            failed_files: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+/// Everything the server knows about a job, persisted as `job.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct JobRecord {
+    pub job_id: String,
+    /// Principal who uploaded the job, when authentication is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    pub status: JobStatus,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub input_files: Vec<String>,
+    pub runs: Vec<JobRun>,
+}
+
+impl JobRecord {
+    pub(crate) fn new(job_id: String, input_files: Vec<String>) -> Self {
+        let now = Utc::now();
+        JobRecord {
+            job_id,
+            owner: None,
+            status: JobStatus::Uploaded,
+            created_at: now,
+            updated_at: now,
+            input_files,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Appends a new run with the next version number and returns it.
+    pub(crate) fn start_run(&mut self, options: JobOptions) -> JobRun {
+        let version =
+            self.runs.iter().map(|r| r.version).max().unwrap_or(0) + 1;
+        let run = JobRun::start(version, options);
+        self.record_run(run.clone());
+        run
+    }
+
+    /// Returns the run with the given version.
+    pub(crate) fn run(&self, version: u32) -> Option<&JobRun> {
+        self.runs.iter().find(|r| r.version == version)
+    }
+
+    /// Drops the runs with the given versions, whose folders were removed.
+    /// The job takes the status of its latest remaining run.
+    pub(crate) fn forget_runs(&mut self, versions: &[u32]) {
+        self.runs.retain(|run| !versions.contains(&run.version));
+        self.status =
+            self.runs.last().map_or(JobStatus::Uploaded, |run| run.status);
+    }
+
+    /// Replaces the run with the same version, or appends it, and makes its
+    /// status the status of the job.
+    pub(crate) fn record_run(&mut self, run: JobRun) {
+        self.status = run.status;
+        self.updated_at = Utc::now();
+        match self.runs.iter_mut().find(|r| r.version == run.version) {
+            Some(existing) => *existing = run,
+            None => self.runs.push(run),
+        }
+    }
+}
+
+/// JSON-per-job metadata store rooted at the upload folder.
+pub(crate) struct JobStore {
+    root: PathBuf,
+    // Serializes read-modify-write cycles on the metadata files
+    lock: Mutex<()>,
+}
+
+impl JobStore {
+    pub(crate) fn new(root: impl Into<PathBuf>) -> Self {
+        JobStore { root: root.into(), lock: Mutex::new(()) }
+    }
+
+    /// Folder holding one sub-folder per job.
+    pub(crate) fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the folder of a job, or `None` if `job_id` is not a valid job
+    /// id. Job ids are UUIDs, so they can never escape the upload folder.
+    pub(crate) fn job_dir(&self, job_id: &str) -> Option<PathBuf> {
+        Uuid::parse_str(job_id).ok().map(|_| self.root.join(job_id))
+    }
+
+    /// Loads the metadata of a job. Job folders created before the store
+    /// existed get a record rebuilt from their content.
+    pub(crate) fn get(&self, job_id: &str) -> io::Result<Option<JobRecord>> {
+        let job_dir = match self.job_dir(job_id) {
+            Some(dir) if dir.is_dir() => dir,
+            _ => return Ok(None),
+        };
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        read_record(&job_dir, job_id).map(Some)
+    }
+
+    /// Writes the metadata of a job.
+    pub(crate) fn save(&self, record: &JobRecord) -> io::Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.write(record)
+    }
+
+    /// Applies `change` to the stored metadata of a job and saves it. Returns
+    /// the updated record, or `None` if the job does not exist.
+    pub(crate) fn update(
+        &self,
+        job_id: &str,
+        change: impl FnOnce(&mut JobRecord),
+    ) -> io::Result<Option<JobRecord>> {
+        let job_dir = match self.job_dir(job_id) {
+            Some(dir) if dir.is_dir() => dir,
+            _ => return Ok(None),
+        };
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut record = read_record(&job_dir, job_id)?;
+        change(&mut record);
+        record.updated_at = Utc::now();
+        self.write(&record)?;
+        Ok(Some(record))
+    }
+
+    /// Loads the metadata of every job in the upload folder. Jobs whose
+    /// metadata cannot be read are skipped.
+    pub(crate) fn list(&self) -> io::Result<Vec<JobRecord>> {
+        if !self.root.is_dir() {
+            return Ok(Vec::new());
+        }
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut records = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            let job_id = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name)
+                    if path.is_dir() && Uuid::parse_str(name).is_ok() =>
+                {
+                    name.to_string()
+                }
+                _ => continue,
+            };
+            match read_record(&path, &job_id) {
+                Ok(record) => records.push(record),
+                Err(err) => {
+                    error!("Failed to read metadata of job {}: {}", job_id, err)
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Moves the uploads of jobs created before inputs had their own folder
+    /// into `inputs/`, leaving their metadata and legacy results in place.
+    /// Returns the number of jobs moved.
+    pub(crate) fn migrate_legacy_inputs(&self) -> io::Result<usize> {
+        if !self.root.is_dir() {
+            return Ok(0);
+        }
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut migrated = 0;
+        for entry in fs::read_dir(&self.root)? {
+            let job_dir = entry?.path();
+            let is_job = job_dir
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| Uuid::parse_str(name).is_ok());
+            if !is_job || !job_dir.is_dir() || job_dir.join(INPUTS_DIR).exists()
+            {
+                continue;
+            }
+            match move_legacy_inputs(&job_dir) {
+                Ok(()) => migrated += 1,
+                Err(err) => error!(
+                    "Failed to move the uploads of '{}': {}",
+                    job_dir.display(),
+                    err
+                ),
+            }
+        }
+        Ok(migrated)
+    }
+
+    /// Marks the runs a stopped server left in progress as failed and
+    /// removes their partial outputs. Returns the number of runs.
+    pub(crate) fn fail_interrupted_runs(&self) -> io::Result<usize> {
+        let mut interrupted = 0;
+        for record in self.list()? {
+            let versions: Vec<u32> = record
+                .runs
+                .iter()
+                .filter(|run| run.status == JobStatus::Processing)
+                .map(|run| run.version)
+                .collect();
+            if versions.is_empty() {
+                continue;
+            }
+            let job_dir = self.root.join(&record.job_id);
+            for version in &versions {
+                let path = run_dir(&job_dir, *version);
+                if let Err(err) = fs::remove_dir_all(&path) {
+                    if err.kind() != io::ErrorKind::NotFound {
+                        warn!("Failed to remove '{}': {}", path.display(), err);
+                    }
+                }
+            }
+
+            let now = Utc::now();
+            self.update(&record.job_id, |record| {
+                for run in &mut record.runs {
+                    if versions.contains(&run.version) {
+                        run.status = JobStatus::Failed;
+                        run.finished_at = Some(now);
+                        run.output_files.clear();
+                        run.rows_deleted.clear();
+                        run.zip_file_name = None;
+                        run.error = Some(INTERRUPTED_MESSAGE.to_string());
+                    }
+                }
+                if record.status == JobStatus::Processing {
+                    record.status = JobStatus::Failed;
+                }
+            })?;
+            info!(
+                "Marked interrupted runs {:?} of job {} as failed",
+                versions, record.job_id
+            );
+            interrupted += versions.len();
+        }
+        Ok(interrupted)
+    }
+
+    fn write(&self, record: &JobRecord) -> io::Result<()> {
+        let job_dir = self.job_dir(&record.job_id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Invalid job id")
+        })?;
+        write_atomic(
+            &job_dir.join(JOB_FILE),
+            &serde_json::to_vec_pretty(record)?,
+        )?;
+        debug!("Saved metadata of job {}", record.job_id);
+        Ok(())
+    }
+}
+
+/// Writes `content` to `path` through a temporary file renamed over it, so a
+/// crash never leaves a truncated file behind.
+pub(crate) fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    fs::write(&temp_path, content)?;
+    fs::rename(&temp_path, path)
+}
+
+fn read_record(job_dir: &Path, job_id: &str) -> io::Result<JobRecord> {
+    match fs::read(job_dir.join(JOB_FILE)) {
+        Ok(content) => Ok(serde_json::from_slice(&content)?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            info!("Rebuilding metadata of job {}", job_id);
+            recover_record(job_dir, job_id)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns the folder holding the uploaded files of a job.
+pub(crate) fn inputs_dir(job_dir: &Path) -> PathBuf {
+    job_dir.join(INPUTS_DIR)
+}
+
+/// Tells whether an entry of a job folder is one of the
+/// `firstsheet{timestamp}` folders and zips written by `/process` in jobs
+/// created before runs had their own folder.
+pub(crate) fn is_legacy_result(name: &str) -> bool {
+    name.starts_with("firstsheet")
+}
+
+/// Returns the folder of one run of a job.
+pub(crate) fn run_dir(job_dir: &Path, version: u32) -> PathBuf {
+    job_dir.join(RUNS_DIR).join(version.to_string())
+}
+
+// Moves the uploaded files of a job folder into its new `inputs/` folder.
+fn move_legacy_inputs(job_dir: &Path) -> io::Result<()> {
+    let inputs_dir = inputs_dir(job_dir);
+    fs::create_dir(&inputs_dir)?;
+    for entry in fs::read_dir(job_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_file()
+            && !name.starts_with(JOB_FILE)
+            && !is_legacy_result(&name)
+        {
+            fs::rename(entry.path(), inputs_dir.join(&name))?;
+        }
+    }
+    Ok(())
+}
+
+// Builds a record for a job folder that has no `job.json`, treating every
+// file of its inputs except legacy result zips as an upload.
+fn recover_record(job_dir: &Path, job_id: &str) -> io::Result<JobRecord> {
+    let mut input_files = Vec::new();
+    for entry in fs::read_dir(inputs_dir(job_dir))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_file() && !is_legacy_result(&name) {
+            input_files.push(name);
+        }
+    }
+    input_files.sort();
+
+    let mut record = JobRecord::new(job_id.to_string(), input_files);
+    if let Ok(created) = fs::metadata(job_dir).and_then(|m| m.modified()) {
+        record.created_at = created.into();
+        record.updated_at = record.created_at;
+    }
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store() -> JobStore {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&root).unwrap();
+        JobStore::new(root)
+    }
+
+    // Creates a job folder with `inputs` uploaded and returns its id.
+    fn create_job(store: &JobStore, inputs: &[&str]) -> String {
+        let job_id = Uuid::new_v4().to_string();
+        let inputs_dir = inputs_dir(&store.root().join(&job_id));
+        fs::create_dir_all(&inputs_dir).unwrap();
+        for name in inputs {
+            fs::write(inputs_dir.join(name), b"data").unwrap();
+        }
+        job_id
+    }
+
+    fn options() -> JobOptions {
+        JobOptions {
+            num_rows_to_delete: 2,
+            auto_detect_rows: false,
+            overrides: Vec::new(),
+            profile: None,
+            profile_version: None,
+        }
+    }
+
+    #[test]
+    fn unsupported_options_are_rejected() {
+        let options: JobOptions = serde_json::from_str(
+            r#"{"num_rows_to_delete": 3, "overrides": [{"pattern": "*"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(options.num_rows_to_delete, 3);
+        assert_eq!(options.overrides.len(), 1);
+
+        for json in [
+            r#"{"columns": ["A", "C"]}"#,
+            r#"{"output_format": "csv"}"#,
+            r#"{"overrides": [{"pattern": "*.xlsx", "filters": []}]}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<JobOptions>(json).is_err(),
+                "{json}"
+            );
+        }
+    }
+
+    #[test]
+    fn saved_records_are_read_back() {
+        let store = temp_store();
+        let job_id = create_job(&store, &["a.xlsx"]);
+        let mut record = JobRecord::new(job_id.clone(), vec!["a.xlsx".into()]);
+        record.owner = Some("alice".into());
+        record.start_run(options());
+        store.save(&record).unwrap();
+
+        let job_dir = store.job_dir(&job_id).unwrap();
+        let names: Vec<_> = fs::read_dir(&job_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name != INPUTS_DIR)
+            .collect();
+        // The temporary file was renamed over `job.json`
+        assert_eq!(names, [JOB_FILE]);
+
+        let read = store.get(&job_id).unwrap().unwrap();
+        assert_eq!(read.owner.as_deref(), Some("alice"));
+        assert_eq!(read.status, JobStatus::Processing);
+        assert_eq!(read.runs.len(), 1);
+        assert_eq!(read.runs[0].options.num_rows_to_delete, 2);
+
+        assert!(store.get(&Uuid::new_v4().to_string()).unwrap().is_none());
+        assert!(store.get("../etc").unwrap().is_none());
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn update_changes_the_stored_record() {
+        let store = temp_store();
+        let job_id = create_job(&store, &["a.xlsx"]);
+        store
+            .save(&JobRecord::new(job_id.clone(), vec!["a.xlsx".into()]))
+            .unwrap();
+
+        let updated = store
+            .update(&job_id, |record| record.owner = Some("bob".into()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated.owner.as_deref(), Some("bob"));
+        let read = store.get(&job_id).unwrap().unwrap();
+        assert_eq!(read.owner.as_deref(), Some("bob"));
+        assert_eq!(read.updated_at, updated.updated_at);
+
+        let missing = Uuid::new_v4().to_string();
+        let mut called = false;
+        assert!(store.update(&missing, |_| called = true).unwrap().is_none());
+        assert!(!called);
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn interrupted_runs_are_marked_failed() {
+        let store = temp_store();
+        let job_id = create_job(&store, &["a.xlsx"]);
+        let mut record = JobRecord::new(job_id.clone(), vec!["a.xlsx".into()]);
+        let mut finished = record.start_run(options());
+        finished.status = JobStatus::Completed;
+        record.record_run(finished);
+        let running = record.start_run(options());
+        store.save(&record).unwrap();
+        let job_dir = store.job_dir(&job_id).unwrap();
+        let partial = run_dir(&job_dir, running.version);
+        fs::create_dir_all(&partial).unwrap();
+        fs::write(partial.join("a.xlsx"), b"partial").unwrap();
+
+        assert_eq!(store.fail_interrupted_runs().unwrap(), 1);
+        assert!(!partial.exists());
+        let read = store.get(&job_id).unwrap().unwrap();
+        assert_eq!(read.status, JobStatus::Failed);
+        assert_eq!(read.runs[0].status, JobStatus::Completed);
+        let run = read.run(running.version).unwrap();
+        assert_eq!(run.status, JobStatus::Failed);
+        assert_eq!(run.error.as_deref(), Some(INTERRUPTED_MESSAGE));
+        assert!(run.finished_at.is_some());
+
+        assert_eq!(store.fail_interrupted_runs().unwrap(), 0);
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn missing_records_are_rebuilt_from_the_inputs() {
+        let store = temp_store();
+        let job_id =
+            create_job(&store, &["b.xlsx", "a.zip", "firstsheet0101.zip"]);
+
+        let record = store.get(&job_id).unwrap().unwrap();
+        assert_eq!(record.job_id, job_id);
+        assert_eq!(record.status, JobStatus::Uploaded);
+        assert_eq!(record.input_files, ["a.zip", "b.xlsx"]);
+        assert!(record.runs.is_empty());
+        // Rebuilding does not write `job.json`
+        assert!(!store.job_dir(&job_id).unwrap().join(JOB_FILE).exists());
+        assert_eq!(store.list().unwrap().len(), 1);
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn legacy_uploads_move_to_inputs() {
+        let store = temp_store();
+        let job_id = Uuid::new_v4().to_string();
+        let job_dir = store.root().join(&job_id);
+        fs::create_dir(&job_dir).unwrap();
+        for name in ["a.xlsx", "b.zip", "firstsheet010124120000.zip"] {
+            fs::write(job_dir.join(name), b"data").unwrap();
+        }
+        fs::create_dir(job_dir.join("firstsheet010124120000")).unwrap();
+
+        assert_eq!(store.migrate_legacy_inputs().unwrap(), 1);
+        let mut inputs: Vec<_> = fs::read_dir(inputs_dir(&job_dir))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        inputs.sort();
+        assert_eq!(inputs, ["a.xlsx", "b.zip"]);
+        assert!(job_dir.join("firstsheet010124120000.zip").is_file());
+        assert!(job_dir.join("firstsheet010124120000").is_dir());
+        let record = store.get(&job_id).unwrap().unwrap();
+        assert_eq!(record.input_files, ["a.xlsx", "b.zip"]);
+
+        // Jobs that have their inputs folder are left alone
+        assert_eq!(store.migrate_legacy_inputs().unwrap(), 0);
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+}
@@ -0,0 +1,40 @@
+//! Excel processing engine behind the `xlsx_manager` server and CLI.
+//!
+//! The engine reads `.xlsx`/`.xls` workbooks, deletes the first rows of their
+//! first sheet, writes the result as `.xlsx` and handles the Zip archives the
+//! files travel in.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use xlsx_manager::{process_excel_files_parallel, process_input_folder, ProcessOptions};
+//!
+//! let source = Path::new("input");
+//! let files = xlsx_manager::list_excel_files_in_directory(source)?;
+//! let options = ProcessOptions {
+//!     delete_first_n_rows: 7,
+//!     ..Default::default()
+//! };
+//! let summary = process_excel_files_parallel(
+//!     source,
+//!     Path::new("output"),
+//!     &files,
+//!     &options,
+//! )?;
+//! assert!(summary.failed.is_empty());
+//! # Ok::<(), xlsx_manager::Error>(())
+//! ```
+
+mod error;
+mod xlsx_manager;
+
+pub use crate::error::{Error, Result};
+pub use crate::xlsx_manager::{
+    create_zip_at, create_zip_from_folder, current_job_id, detect_header_rows,
+    excel_serial_to_naive_datetime, extract_zip_file, extract_zip_file_to,
+    list_excel_files_in_directory, preview_workbook,
+    process_excel_files_parallel, process_excel_files_with_progress,
+    process_input_folder, process_input_folder_with_progress,
+    process_single_excel, with_job_id, CancelFlag, FileFailure, FileOverride,
+    MemoryBudget, PreviewCell, ProcessOptions, ProcessingSummary,
+    ProgressEvent, SheetPreview, WorkbookPreview, ZipLimits,
+};
@@ -0,0 +1,353 @@
+use crate::config::LimitsConfig;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Number of clients tracked before idle ones are forgotten
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+// Requests a client may still make, refilled continuously.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token-bucket limit on the requests of each client.
+pub(crate) struct RateLimiter {
+    // Tokens added per second, 0 when unlimited
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: &LimitsConfig) -> Self {
+        RateLimiter {
+            rate: config.requests_per_minute as f64 / 60.0,
+            burst: config.burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a request of `client`. Returns how long to wait before the next
+    /// request is allowed if the client is over its limit.
+    pub(crate) fn check(&self, client: &str) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        if self.rate == 0.0 {
+            return Ok(());
+        }
+        let mut buckets =
+            self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            // Clients whose bucket refilled are the same as new ones
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst);
+        }
+
+        let bucket = buckets
+            .entry(client.to_string())
+            .or_insert(Bucket { tokens: self.burst, updated: now });
+        let tokens = self.refill(bucket, now);
+        if tokens >= 1.0 {
+            bucket.tokens = tokens - 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - tokens) / self.rate))
+        }
+    }
+
+    /// Returns how long `client` must wait before its next request is
+    /// allowed, without counting a request, or `None` if it may go ahead.
+    pub(crate) fn wait_time(&self, client: &str) -> Option<Duration> {
+        self.wait_time_at(client, Instant::now())
+    }
+
+    fn wait_time_at(&self, client: &str, now: Instant) -> Option<Duration> {
+        if self.rate == 0.0 {
+            return None;
+        }
+        let mut buckets =
+            self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let tokens = self.refill(buckets.get_mut(client)?, now);
+        (tokens < 1.0)
+            .then(|| Duration::from_secs_f64((1.0 - tokens) / self.rate))
+    }
+
+    // Adds the tokens earned since the last update and returns the total.
+    fn refill(&self, bucket: &mut Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        bucket.tokens
+    }
+}
+
+/// Why a job could not get a slot.
+#[derive(Debug)]
+pub(crate) enum SlotError {
+    /// Too many jobs are already waiting
+    QueueFull,
+    /// No slot freed up within the queue timeout
+    Timeout,
+}
+
+/// Global cap on the jobs running at once, with a bounded queue of jobs
+/// waiting for a slot.
+pub(crate) struct JobSlots {
+    // `None` when the number of running jobs is unlimited
+    semaphore: Option<Arc<Semaphore>>,
+    max_queued: usize,
+    queued: AtomicUsize,
+    timeout: Duration,
+}
+
+impl JobSlots {
+    pub(crate) fn new(config: &LimitsConfig) -> Self {
+        JobSlots {
+            semaphore: (config.max_running_jobs > 0)
+                .then(|| Arc::new(Semaphore::new(config.max_running_jobs))),
+            max_queued: config.max_queued_jobs,
+            queued: AtomicUsize::new(0),
+            timeout: Duration::from_secs(config.queue_timeout),
+        }
+    }
+
+    /// Waits for a slot to run a job in. The slot is freed when the returned
+    /// permit is dropped.
+    pub(crate) async fn acquire(
+        &self,
+    ) -> Result<Option<OwnedSemaphorePermit>, SlotError> {
+        let semaphore = match &self.semaphore {
+            Some(semaphore) => semaphore.clone(),
+            None => return Ok(None),
+        };
+        if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+            return Ok(Some(permit));
+        }
+
+        // Join the queue unless it is full. The guard leaves it even if the
+        // request is dropped while waiting.
+        let _queued = QueueGuard(&self.queued);
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+            return Err(SlotError::QueueFull);
+        }
+        let permit = actix_web::rt::time::timeout(
+            self.timeout,
+            semaphore.acquire_owned(),
+        )
+        .await;
+        match permit {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            // The semaphore is never closed
+            Ok(Err(_)) | Err(_) => Err(SlotError::Timeout),
+        }
+    }
+
+    /// Checks whether a new job would be turned away right now, because
+    /// every slot is taken and the queue is full.
+    pub(crate) fn is_saturated(&self) -> bool {
+        match &self.semaphore {
+            Some(semaphore) => {
+                semaphore.available_permits() == 0
+                    && self.queued.load(Ordering::SeqCst) >= self.max_queued
+            }
+            None => false,
+        }
+    }
+
+    /// Suggested delay before retrying a job that got no slot.
+    pub(crate) fn retry_after(&self) -> Duration {
+        self.timeout.max(Duration::from_secs(1))
+    }
+}
+
+// Counts a request out of the queue when dropped.
+struct QueueGuard<'a>(&'a AtomicUsize);
+
+impl Drop for QueueGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::poll;
+    use std::pin::pin;
+
+    fn limits(requests_per_minute: u32, burst: u32) -> LimitsConfig {
+        LimitsConfig { requests_per_minute, burst, ..LimitsConfig::default() }
+    }
+
+    fn slots(max_running_jobs: usize, max_queued_jobs: usize) -> JobSlots {
+        JobSlots::new(&LimitsConfig {
+            max_running_jobs,
+            max_queued_jobs,
+            queue_timeout: 60,
+            ..LimitsConfig::default()
+        })
+    }
+
+    fn tracked_clients(limiter: &RateLimiter) -> usize {
+        limiter.buckets.lock().unwrap().len()
+    }
+
+    #[test]
+    fn rate_limiter_allows_a_burst_then_refills() {
+        // One request per second, three in a row
+        let limiter = RateLimiter::new(&limits(60, 3));
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at("a", start), Ok(()));
+        }
+        assert_eq!(limiter.check_at("a", start), Err(Duration::from_secs(1)));
+
+        // Half a token earned: wait for the other half
+        let later = start + Duration::from_millis(500);
+        assert_eq!(
+            limiter.check_at("a", later),
+            Err(Duration::from_millis(500))
+        );
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.check_at("a", later), Ok(()));
+        assert!(limiter.check_at("a", later).is_err());
+
+        // Refilling stops at the burst
+        let later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at("a", later), Ok(()));
+        }
+        assert!(limiter.check_at("a", later).is_err());
+    }
+
+    #[test]
+    fn wait_time_does_not_count_a_request() {
+        let limiter = RateLimiter::new(&limits(60, 2));
+        let now = Instant::now();
+        assert_eq!(limiter.wait_time_at("a", now), None);
+
+        assert!(limiter.check_at("a", now).is_ok());
+        assert_eq!(limiter.wait_time_at("a", now), None);
+        assert!(limiter.check_at("a", now).is_ok());
+        assert_eq!(
+            limiter.wait_time_at("a", now),
+            Some(Duration::from_secs(1))
+        );
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.wait_time_at("a", later), None);
+        assert!(limiter.check_at("a", later).is_ok());
+    }
+
+    #[test]
+    fn rate_limiter_counts_clients_separately() {
+        let limiter = RateLimiter::new(&limits(60, 1));
+        let now = Instant::now();
+        assert_eq!(limiter.check_at("a", now), Ok(()));
+        assert!(limiter.check_at("a", now).is_err());
+        assert_eq!(limiter.check_at("b", now), Ok(()));
+    }
+
+    #[test]
+    fn rate_limiter_without_rate_is_unlimited() {
+        let limiter = RateLimiter::new(&limits(0, 1));
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert_eq!(limiter.check_at("a", now), Ok(()));
+        }
+        assert_eq!(tracked_clients(&limiter), 0);
+    }
+
+    #[test]
+    fn rate_limiter_forgets_refilled_clients_when_full() {
+        let limiter = RateLimiter::new(&limits(60, 2));
+        let start = Instant::now();
+        for client in 0..MAX_TRACKED_CLIENTS {
+            limiter.check_at(&client.to_string(), start).unwrap();
+        }
+        // One client used up its burst
+        limiter.check_at("0", start).unwrap();
+        assert_eq!(tracked_clients(&limiter), MAX_TRACKED_CLIENTS);
+
+        // Nobody refilled yet, so nobody is forgotten
+        limiter.check_at("new", start).unwrap();
+        assert_eq!(tracked_clients(&limiter), MAX_TRACKED_CLIENTS + 1);
+
+        // One second later, only the client that used its burst is still
+        // below it and remembered along with the client that just came in
+        let later = start + Duration::from_secs(1);
+        limiter.check_at("newer", later).unwrap();
+        assert_eq!(tracked_clients(&limiter), 2);
+        assert!(limiter.check_at("0", later).is_ok());
+        assert!(limiter.check_at("0", later).is_err());
+    }
+
+    #[actix_web::test]
+    async fn job_slots_queue_jobs_until_full() {
+        let slots = slots(1, 1);
+        let running = slots.acquire().await.unwrap();
+        assert!(running.is_some());
+        assert!(!slots.is_saturated());
+
+        // The next job waits in the queue, which is then full
+        let mut waiting = pin!(slots.acquire());
+        assert!(poll!(waiting.as_mut()).is_pending());
+        assert!(slots.is_saturated());
+        assert!(matches!(slots.acquire().await, Err(SlotError::QueueFull)));
+
+        // It gets the slot once the running job is done
+        drop(running);
+        let next = waiting.await.unwrap();
+        assert!(next.is_some());
+        assert!(!slots.is_saturated());
+        assert_eq!(slots.queued.load(Ordering::SeqCst), 0);
+    }
+
+    #[actix_web::test]
+    async fn job_slots_leave_the_queue_when_dropped() {
+        let slots = slots(1, 1);
+        let _running = slots.acquire().await.unwrap();
+        {
+            let mut waiting = pin!(slots.acquire());
+            assert!(poll!(waiting.as_mut()).is_pending());
+            assert!(slots.is_saturated());
+        }
+        assert_eq!(slots.queued.load(Ordering::SeqCst), 0);
+        assert!(!slots.is_saturated());
+    }
+
+    #[actix_web::test]
+    async fn job_slots_without_queue_turn_jobs_away() {
+        let slots = slots(2, 0);
+        let _first = slots.acquire().await.unwrap();
+        assert!(!slots.is_saturated());
+        let _second = slots.acquire().await.unwrap();
+        assert!(slots.is_saturated());
+        assert!(matches!(slots.acquire().await, Err(SlotError::QueueFull)));
+    }
+
+    #[actix_web::test]
+    async fn job_slots_time_out() {
+        let slots = JobSlots::new(&LimitsConfig {
+            max_running_jobs: 1,
+            queue_timeout: 0,
+            ..LimitsConfig::default()
+        });
+        let _running = slots.acquire().await.unwrap();
+        assert!(matches!(slots.acquire().await, Err(SlotError::Timeout)));
+        assert_eq!(slots.queued.load(Ordering::SeqCst), 0);
+    }
+
+    #[actix_web::test]
+    async fn job_slots_without_limit_never_saturate() {
+        let slots = slots(0, 0);
+        for _ in 0..10 {
+            assert!(matches!(slots.acquire().await, Ok(None)));
+        }
+        assert!(!slots.is_saturated());
+    }
+}
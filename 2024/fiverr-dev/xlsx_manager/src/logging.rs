@@ -0,0 +1,140 @@
+use crate::config::LogFormat;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use env_logger::fmt::Formatter;
+use log::Record;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::future::Future;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use uuid::Uuid;
+use xlsx_manager::current_job_id;
+
+/// Header carrying the id of a request, both ways
+pub(crate) const REQUEST_ID_HEADER: HeaderName =
+    HeaderName::from_static("x-request-id");
+/// Longest request id accepted from a client
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+// Whether lines are written as JSON, set once the configuration is loaded
+static JSON_FORMAT: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // Id of the request being handled on this thread
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Initializes the logger with the default log level as "info". Lines are
+/// text until [`set_format`] is called.
+pub(crate) fn init() {
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or("info"),
+    )
+    .format(write_record)
+    .init();
+}
+
+pub(crate) fn set_format(format: LogFormat) {
+    JSON_FORMAT.store(matches!(format, LogFormat::Json), Ordering::Relaxed);
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    timestamp: String,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    job_id: Option<String>,
+}
+
+// Writes a record with the ids of the request and job it was logged for.
+fn write_record(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let request_id = REQUEST_ID.with(|id| id.borrow().clone());
+    let job_id = current_job_id();
+
+    if JSON_FORMAT.load(Ordering::Relaxed) {
+        let line = JsonLine {
+            timestamp: buf.timestamp_millis().to_string(),
+            level: record.level().as_str(),
+            target: record.target(),
+            message: record.args().to_string(),
+            request_id,
+            job_id,
+        };
+        let line = serde_json::to_string(&line).map_err(io::Error::other)?;
+        return writeln!(buf, "{}", line);
+    }
+
+    write!(
+        buf,
+        "[{} {:<5} {}",
+        buf.timestamp(),
+        record.level(),
+        record.target()
+    )?;
+    if let Some(request_id) = request_id {
+        write!(buf, " request_id={}", request_id)?;
+    }
+    if let Some(job_id) = job_id {
+        write!(buf, " job_id={}", job_id)?;
+    }
+    writeln!(buf, "] {}", record.args())
+}
+
+/// Middleware giving each request an id, added to the lines logged while
+/// handling it and returned in the `X-Request-Id` header. A valid id sent by
+/// the client in that header is kept.
+pub(crate) async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let mut res = WithRequestId {
+        request_id: request_id.clone(),
+        inner: Box::pin(next.call(req)),
+    }
+    .await?;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(res)
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    (1..=MAX_REQUEST_ID_LEN).contains(&id.len())
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+}
+
+// Future setting the request id of the thread each time it is polled, so
+// that requests interleaved on a worker keep their own id.
+struct WithRequestId<F> {
+    request_id: String,
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for WithRequestId<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = &mut *self;
+        let previous = REQUEST_ID.replace(Some(this.request_id.clone()));
+        let poll = this.inner.as_mut().poll(cx);
+        REQUEST_ID.set(previous);
+        poll
+    }
+}
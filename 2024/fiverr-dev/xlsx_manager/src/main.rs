@@ -1,40 +1,206 @@
+use crate::cli::{Cli, Command};
+use crate::config::{is_any, CorsConfig};
+use crate::jobs::JobStore;
+use crate::limits::{JobSlots, RateLimiter};
+use crate::metrics::Metrics;
+use crate::pool::ProcessingPool;
+use crate::profiles::ProfileStore;
+use crate::progress::ProgressHub;
 use crate::routes::{api, index};
+use crate::shutdown::Shutdown;
 use actix_cors::Cors;
-use actix_web::{App, HttpServer};
-use log::info;
+use actix_web::{middleware, web, App, HttpServer};
+use clap::Parser;
+use futures::future::{select, Either};
+use log::{error, info, warn};
+use std::future::Future;
+use std::pin::pin;
+use std::time::Duration;
 
+mod cli;
 mod config;
+mod janitor;
+mod jobs;
+mod limits;
+mod logging;
+mod metrics;
+mod pool;
+mod profiles;
+mod progress;
 mod routes;
-mod xlsx_manager;
+mod shutdown;
+mod tls;
+
+fn main() -> std::io::Result<()> {
+    logging::init();
+
+    // Run the requested subcommand, starting the server by default
+    match Cli::parse().command {
+        Some(Command::Process(args)) => {
+            std::process::exit(cli::run_process(&args))
+        }
+        Some(Command::Serve) | None => run_server(),
+    }
+}
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    // Initialize the logger with the default log level as "info"
-    env_logger::Builder::from_env(
-        env_logger::Env::default().default_filter_or("info"),
-    )
-    .init();
+async fn run_server() -> std::io::Result<()> {
     info!("Starting HttpServer..");
 
     // Load the configuration
     let config =
         config::AppConfig::from_env().expect("Failed to load configuration");
+    logging::set_format(config.logging.format);
     info!("Using configuration: {:#?}", config);
+    let config = web::Data::new(config);
+
+    if is_any(&config.cors.allowed_origins) {
+        warn!(
+            "CORS allows requests from any origin; use it for development only"
+        );
+    }
+    let app_config = config.clone();
+    let job_store = web::Data::new(JobStore::new(jobs::UPLOAD_DIR));
+    let profile_store = web::Data::new(ProfileStore::new(&config.profiles.dir));
+    let progress_hub = web::Data::new(ProgressHub::default());
+    // Expire old job data in the background
+    actix_web::rt::spawn(janitor::run(
+        config.janitor.clone(),
+        job_store.clone(),
+        progress_hub.clone(),
+    ));
+    let rate_limiter = web::Data::new(RateLimiter::new(&config.limits));
+    let job_slots = web::Data::new(JobSlots::new(&config.limits));
+    let processing_pool =
+        web::Data::new(ProcessingPool::new(&config.processing)?);
+    info!("Processing files on {} threads", processing_pool.threads());
+    let metrics = web::Data::new(Metrics::new());
+    let shutdown = web::Data::new(Shutdown::default());
 
-    let server = HttpServer::new(|| {
+    // Give the jobs of older versions the current folder layout
+    match job_store.migrate_legacy_inputs() {
+        Ok(0) => {}
+        Ok(count) => info!("Moved the uploads of {} jobs to inputs", count),
+        Err(err) => error!("Failed to move legacy uploads: {}", err),
+    }
+    // Fail the runs a previous server stopped in the middle of
+    match job_store.fail_interrupted_runs() {
+        Ok(0) => {}
+        Ok(count) => warn!("Marked {} interrupted runs as failed", count),
+        Err(err) => error!("Failed to check for interrupted runs: {}", err),
+    }
+    let watch_shutdown = {
+        let shutdown = shutdown.clone();
+        let progress_hub = progress_hub.clone();
+        let timeout = Duration::from_secs(config.server.shutdown_timeout);
+        move |servers| shutdown::watch(shutdown, progress_hub, servers, timeout)
+    };
+
+    let server = HttpServer::new(move || {
         App::new()
-            .wrap(
-                Cors::default()
-                    .allow_any_origin() // Allow any origin
-                    .allow_any_method() // Allow any HTTP method
-                    .allow_any_header(), // Allow any header
-            )
+            .app_data(app_config.clone())
+            .app_data(job_store.clone())
+            .app_data(profile_store.clone())
+            .app_data(progress_hub.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(job_slots.clone())
+            .app_data(processing_pool.clone())
+            .app_data(metrics.clone())
+            .app_data(shutdown.clone())
+            .wrap(cors(&app_config.cors))
+            .wrap(middleware::from_fn(metrics::track_requests))
+            .wrap(middleware::from_fn(logging::request_id))
             .configure(index::configure_routes)
             .configure(api::configure_routes)
+            .configure(routes::profiles::configure_routes)
+            .configure(routes::metrics::configure_routes)
+            .configure(routes::health::configure_routes)
     })
-    .bind((config.server.host, config.server.port))?
     .workers(config.server.workers)
     .shutdown_timeout(config.server.shutdown_timeout)
+    // Signals are handled by `shutdown::watch`, which drains the jobs first
+    .disable_signals();
+    let address = (config.server.host.clone(), config.server.port);
+
+    let tls_config = match &config.tls {
+        Some(tls_config) => tls_config,
+        None => {
+            let server = server.bind(address)?.run();
+            let shutdown = watch_shutdown(vec![server.handle()]);
+            return serve_until_shutdown(server, shutdown).await;
+        }
+    };
+
+    // Serve HTTPS, reloading the certificate when its files change
+    let (server_config, resolver) = tls::server_config(tls_config)?;
+    actix_web::rt::spawn(tls::watch(tls_config.clone(), resolver));
+    info!("Serving HTTPS with certificate {}", tls_config.cert.display());
+    let server = server.bind_rustls_0_23(address, server_config)?.run();
+
+    let redirect_port = match tls_config.redirect_port {
+        Some(port) => port,
+        None => {
+            let shutdown = watch_shutdown(vec![server.handle()]);
+            return serve_until_shutdown(server, shutdown).await;
+        }
+    };
+    info!("Redirecting HTTP on port {} to HTTPS", redirect_port);
+    let https_port = web::Data::new(config.server.port);
+    let redirect_server = HttpServer::new(move || {
+        App::new()
+            .app_data(https_port.clone())
+            .default_service(web::to(tls::redirect))
+    })
+    .workers(1)
+    .shutdown_timeout(config.server.shutdown_timeout)
+    .disable_signals()
+    .bind((config.server.host.clone(), redirect_port))?
     .run();
-    server.await
+    let shutdown =
+        watch_shutdown(vec![server.handle(), redirect_server.handle()]);
+    let servers = async {
+        futures::future::try_join(server, redirect_server).await.map(|_| ())
+    };
+    serve_until_shutdown(servers, shutdown).await
+}
+
+// Serves until the servers stop, or until the shutdown stops waiting for
+// their last requests.
+async fn serve_until_shutdown(
+    servers: impl Future<Output = std::io::Result<()>>,
+    shutdown: impl Future<Output = ()>,
+) -> std::io::Result<()> {
+    match select(pin!(servers), pin!(shutdown)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(((), _)) => Ok(()),
+    }
+}
+
+// Builds the CORS middleware from the validated `[cors]` settings.
+fn cors(config: &CorsConfig) -> Cors {
+    // Let browsers read the id of their requests
+    let mut cors = Cors::default()
+        .max_age(config.max_age)
+        .expose_headers([logging::REQUEST_ID_HEADER]);
+    if is_any(&config.allowed_origins) {
+        cors = cors.allow_any_origin();
+    } else {
+        for origin in &config.allowed_origins {
+            cors = cors.allowed_origin(origin);
+        }
+    }
+    cors = if is_any(&config.allowed_methods) {
+        cors.allow_any_method()
+    } else {
+        cors.allowed_methods(config.allowed_methods.iter().map(String::as_str))
+    };
+    cors = if is_any(&config.allowed_headers) {
+        cors.allow_any_header()
+    } else {
+        cors.allowed_headers(config.allowed_headers.iter().map(String::as_str))
+    };
+    if config.supports_credentials {
+        cors = cors.supports_credentials();
+    }
+    cors
 }
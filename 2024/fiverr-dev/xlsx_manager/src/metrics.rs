@@ -0,0 +1,206 @@
+use crate::jobs::{JobRecord, JobStatus};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web;
+use log::error;
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::time::{Duration, Instant};
+use xlsx_manager::ProgressEvent;
+
+/// Prefix of every metric name
+const NAMESPACE: &str = "xlsx_manager";
+
+/// Metrics exposed in the Prometheus text format by `GET /metrics`.
+pub(crate) struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    files_processed: IntCounter,
+    files_failed: IntCounter,
+    rows_processed: IntCounter,
+    bytes_uploaded: IntCounter,
+    bytes_downloaded: IntCounter,
+    zip_extraction_duration: Histogram,
+    jobs: IntGaugeVec,
+    upload_dir_bytes: IntGauge,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        let metrics = Metrics {
+            registry: Registry::new_custom(Some(NAMESPACE.to_string()), None)
+                .expect("valid metrics namespace"),
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests handled"),
+                &["method", "route", "status"],
+            )
+            .expect("valid metric"),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "Time taken to handle HTTP requests",
+                ),
+                &["method", "route"],
+            )
+            .expect("valid metric"),
+            files_processed: IntCounter::new(
+                "files_processed_total",
+                "Excel files processed",
+            )
+            .expect("valid metric"),
+            files_failed: IntCounter::new(
+                "files_failed_total",
+                "Excel and ZIP files that failed to process",
+            )
+            .expect("valid metric"),
+            rows_processed: IntCounter::new(
+                "rows_processed_total",
+                "Rows written to processed files",
+            )
+            .expect("valid metric"),
+            bytes_uploaded: IntCounter::new(
+                "uploaded_bytes_total",
+                "Bytes of files received by /upload",
+            )
+            .expect("valid metric"),
+            bytes_downloaded: IntCounter::new(
+                "downloaded_bytes_total",
+                "Bytes of ZIP files sent by the download endpoints",
+            )
+            .expect("valid metric"),
+            zip_extraction_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "zip_extraction_duration_seconds",
+                    "Time taken to extract the ZIP files of a run",
+                )
+                .buckets(
+                    exponential_buckets(0.01, 4.0, 8).expect("valid buckets"),
+                ),
+            )
+            .expect("valid metric"),
+            jobs: IntGaugeVec::new(
+                Opts::new("jobs", "Jobs in the upload folder by status"),
+                &["status"],
+            )
+            .expect("valid metric"),
+            upload_dir_bytes: IntGauge::new(
+                "upload_dir_bytes",
+                "Disk space used by the upload folder",
+            )
+            .expect("valid metric"),
+        };
+
+        let collectors: [Box<dyn prometheus::core::Collector>; 10] = [
+            Box::new(metrics.http_requests.clone()),
+            Box::new(metrics.http_request_duration.clone()),
+            Box::new(metrics.files_processed.clone()),
+            Box::new(metrics.files_failed.clone()),
+            Box::new(metrics.rows_processed.clone()),
+            Box::new(metrics.bytes_uploaded.clone()),
+            Box::new(metrics.bytes_downloaded.clone()),
+            Box::new(metrics.zip_extraction_duration.clone()),
+            Box::new(metrics.jobs.clone()),
+            Box::new(metrics.upload_dir_bytes.clone()),
+        ];
+        for collector in collectors {
+            metrics
+                .registry
+                .register(collector)
+                .expect("metric registered once");
+        }
+        metrics
+    }
+
+    pub(crate) fn observe_request(
+        &self,
+        method: &str,
+        route: &str,
+        status: u16,
+        duration: Duration,
+    ) {
+        self.http_requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+        self.http_request_duration
+            .with_label_values(&[method, route])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Counts the files, rows and ZIP extractions of a run.
+    pub(crate) fn record(&self, event: &ProgressEvent) {
+        match event {
+            ProgressEvent::FileFinished { rows_written, .. } => {
+                self.files_processed.inc();
+                self.rows_processed.inc_by(*rows_written as u64);
+            }
+            ProgressEvent::FileFailed { .. } => self.files_failed.inc(),
+            ProgressEvent::ZipExtracted { duration_ms, .. } => self
+                .zip_extraction_duration
+                .observe(*duration_ms as f64 / 1000.0),
+            ProgressEvent::Started { .. }
+            | ProgressEvent::FileStarted { .. } => {}
+        }
+    }
+
+    pub(crate) fn add_uploaded(&self, bytes: u64) {
+        self.bytes_uploaded.inc_by(bytes);
+    }
+
+    pub(crate) fn add_downloaded(&self, bytes: u64) {
+        self.bytes_downloaded.inc_by(bytes);
+    }
+
+    /// Encodes every metric in the Prometheus text format, with the job and
+    /// disk gauges set from `jobs` and `upload_dir_bytes`.
+    pub(crate) fn render(
+        &self,
+        jobs: &[JobRecord],
+        upload_dir_bytes: u64,
+    ) -> String {
+        for status in JobStatus::ALL {
+            let count = jobs.iter().filter(|job| job.status == status).count();
+            self.jobs.with_label_values(&[status.as_str()]).set(count as i64);
+        }
+        self.upload_dir_bytes.set(upload_dir_bytes as i64);
+
+        let mut buffer = Vec::new();
+        if let Err(err) =
+            TextEncoder::new().encode(&self.registry.gather(), &mut buffer)
+        {
+            error!("Failed to encode metrics: {}", err);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Middleware counting the requests of each route and timing them.
+pub(crate) async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let start = Instant::now();
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let method = req.method().to_string();
+    let res = next.call(req).await?;
+
+    if let Some(metrics) = metrics {
+        // Label by route pattern, such as `/jobs/{job_id}`, so that job ids
+        // do not each make a series
+        let route = res
+            .request()
+            .match_pattern()
+            .unwrap_or_else(|| "unmatched".to_string());
+        metrics.observe_request(
+            &method,
+            &route,
+            res.status().as_u16(),
+            start.elapsed(),
+        );
+    }
+    Ok(res)
+}
@@ -0,0 +1,49 @@
+use crate::config::ProcessingConfig;
+use actix_web::error::BlockingError;
+use actix_web::web;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::io;
+use std::sync::Arc;
+use xlsx_manager::MemoryBudget;
+
+/// Threads running the CPU-heavy Excel work of the jobs, so that it neither
+/// blocks the HTTP workers nor competes for rayon's global pool.
+pub(crate) struct ProcessingPool {
+    pool: Arc<ThreadPool>,
+    memory_budget: MemoryBudget,
+}
+
+impl ProcessingPool {
+    pub(crate) fn new(config: &ProcessingConfig) -> io::Result<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(config.threads)
+            .thread_name(|index| format!("processing-{}", index))
+            .build()
+            .map_err(io::Error::other)?;
+        Ok(ProcessingPool {
+            pool: Arc::new(pool),
+            memory_budget: MemoryBudget::new(config.memory_budget),
+        })
+    }
+
+    pub(crate) fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Memory shared by the files of every job run in the pool.
+    pub(crate) fn memory_budget(&self) -> &MemoryBudget {
+        &self.memory_budget
+    }
+
+    /// Runs `work` in the pool and waits for it without blocking the calling
+    /// worker. Parallel iterators used by `work` run on the pool's threads.
+    /// Fails if `work` panics.
+    pub(crate) async fn run<F, R>(&self, work: F) -> Result<R, BlockingError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let pool = self.pool.clone();
+        web::block(move || pool.install(work)).await
+    }
+}
@@ -0,0 +1,160 @@
+use crate::jobs::{write_atomic, JobOptions};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Longest accepted profile name, in characters
+const MAX_NAME_LEN: usize = 64;
+
+/// One saved version of a profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ProfileVersion {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub options: JobOptions,
+}
+
+/// A named set of processing options and its history. Saving a profile
+/// again adds a version; older versions are kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Profile {
+    pub name: String,
+    /// Principal that created the profile, `None` without authentication
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    pub versions: Vec<ProfileVersion>,
+}
+
+impl Profile {
+    /// Returns the given version, or the latest one.
+    pub(crate) fn version(
+        &self,
+        version: Option<u32>,
+    ) -> Option<&ProfileVersion> {
+        match version {
+            Some(version) => {
+                self.versions.iter().find(|v| v.version == version)
+            }
+            None => self.versions.last(),
+        }
+    }
+}
+
+/// Profiles stored as one TOML file per profile, `{dir}/{name}.toml`.
+pub(crate) struct ProfileStore {
+    dir: PathBuf,
+    // Serializes read-modify-write cycles on the profile files
+    lock: Mutex<()>,
+}
+
+impl ProfileStore {
+    pub(crate) fn new(dir: impl Into<PathBuf>) -> Self {
+        ProfileStore { dir: dir.into(), lock: Mutex::new(()) }
+    }
+
+    /// Checks that `name` can be used as a profile name: 1 to 64 letters,
+    /// digits, `-` or `_`.
+    pub(crate) fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= MAX_NAME_LEN
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    /// Loads a profile, or `None` if there is no profile with that name.
+    pub(crate) fn get(&self, name: &str) -> io::Result<Option<Profile>> {
+        if !Self::is_valid_name(name) {
+            return Ok(None);
+        }
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.read(name)
+    }
+
+    /// Loads every profile, sorted by name.
+    pub(crate) fn list(&self) -> io::Result<Vec<Profile>> {
+        if !self.dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut profiles = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) if Self::is_valid_name(name) => name.to_string(),
+                _ => continue,
+            };
+            profiles.extend(self.read(&name)?);
+        }
+        profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(profiles)
+    }
+
+    /// Saves `options` as the next version of the profile `name`, creating
+    /// it for `owner` if needed, and returns the profile. Returns `None`
+    /// without saving if the profile exists and `may_update` refuses it.
+    pub(crate) fn save(
+        &self,
+        name: &str,
+        owner: Option<&str>,
+        description: Option<String>,
+        options: JobOptions,
+        may_update: impl FnOnce(&Profile) -> bool,
+    ) -> io::Result<Option<Profile>> {
+        if !Self::is_valid_name(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid profile name",
+            ));
+        }
+        let options =
+            JobOptions { profile: None, profile_version: None, ..options };
+
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut profile = match self.read(name)? {
+            Some(profile) if !may_update(&profile) => return Ok(None),
+            Some(profile) => profile,
+            None => Profile {
+                name: name.to_string(),
+                owner: owner.map(str::to_string),
+                versions: Vec::new(),
+            },
+        };
+        let version = profile.versions.last().map_or(0, |v| v.version) + 1;
+        profile.versions.push(ProfileVersion {
+            version,
+            created_at: Utc::now(),
+            description,
+            options,
+        });
+
+        fs::create_dir_all(&self.dir)?;
+        let content = toml::to_string_pretty(&profile)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        write_atomic(
+            &self.dir.join(format!("{}.toml", name)),
+            content.as_bytes(),
+        )?;
+        info!("Saved version {} of profile {}", version, name);
+        Ok(Some(profile))
+    }
+
+    fn read(&self, name: &str) -> io::Result<Option<Profile>> {
+        match fs::read_to_string(self.dir.join(format!("{}.toml", name))) {
+            Ok(content) => toml::from_str(&content)
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
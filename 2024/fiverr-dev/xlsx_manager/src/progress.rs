@@ -0,0 +1,303 @@
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use xlsx_manager::{CancelFlag, ProgressEvent};
+
+/// Progress of a running job, as streamed by `GET /jobs/{job_id}/events`.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct JobEvent {
+    pub job_id: String,
+    pub version: u32,
+    /// Share of the files of the run that are done, from 0 to 100
+    pub percent: u8,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+impl JobEvent {
+    /// Name of the event, e.g. `file_finished`.
+    pub(crate) fn name(&self) -> &'static str {
+        match &self.kind {
+            EventKind::Progress(event) => match event {
+                ProgressEvent::Started { .. } => "started",
+                ProgressEvent::FileStarted { .. } => "file_started",
+                ProgressEvent::FileFinished { .. } => "file_finished",
+                ProgressEvent::FileFailed { .. } => "file_failed",
+                ProgressEvent::ZipExtracted { .. } => "zip_extracted",
+            },
+            EventKind::Stage(stage) => match stage {
+                RunStage::RunStarted => "run_started",
+                RunStage::Zipping => "zipping",
+                RunStage::Completed { .. } => "completed",
+                RunStage::Failed { .. } => "failed",
+                RunStage::Cancelled => "cancelled",
+            },
+        }
+    }
+
+    /// Formats the event as a server-sent event.
+    pub(crate) fn to_sse(&self) -> String {
+        let data = serde_json::to_string(self).unwrap_or_default();
+        format!("event: {}\ndata: {}\n\n", self.name(), data)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub(crate) enum EventKind {
+    /// Reported by the engine while it processes the files
+    Progress(ProgressEvent),
+    Stage(RunStage),
+}
+
+/// Stages of a run around the processing of its files.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum RunStage {
+    RunStarted,
+    /// Every file is done and the output ZIP file is being written
+    Zipping,
+    Completed {
+        zip_file_name: String,
+    },
+    Failed {
+        code: String,
+        message: String,
+    },
+    /// The run was stopped by `POST /jobs/{job_id}/cancel`
+    Cancelled,
+}
+
+impl RunStage {
+    fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            RunStage::Completed { .. }
+                | RunStage::Failed { .. }
+                | RunStage::Cancelled
+        )
+    }
+}
+
+// State of the run of one job.
+struct RunProgress {
+    version: u32,
+    cancel: CancelFlag,
+    total_files: usize,
+    done_files: usize,
+    // Every event of the run so far, replayed to late subscribers
+    events: Vec<JobEvent>,
+    subscribers: Vec<UnboundedSender<JobEvent>>,
+}
+
+/// Tracks the running jobs, one run per job, and fans their progress out to
+/// their event streams.
+#[derive(Default)]
+pub(crate) struct ProgressHub {
+    runs: Mutex<HashMap<String, RunProgress>>,
+}
+
+impl ProgressHub {
+    /// Marks a job as running, or returns `None` if it already is.
+    pub(crate) fn reserve(&self, job_id: &str) -> Option<Reservation<'_>> {
+        let mut runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+        if runs.contains_key(job_id) {
+            return None;
+        }
+        let cancel = CancelFlag::new();
+        runs.insert(
+            job_id.to_string(),
+            RunProgress {
+                version: 0,
+                cancel: cancel.clone(),
+                total_files: 0,
+                done_files: 0,
+                events: Vec::new(),
+                subscribers: Vec::new(),
+            },
+        );
+        Some(Reservation {
+            hub: self,
+            job_id: job_id.to_string(),
+            cancel,
+            started: false,
+        })
+    }
+
+    fn start(&self, job_id: &str, version: u32) {
+        let mut runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(run) = runs.get_mut(job_id) {
+            run.version = version;
+        }
+        drop(runs);
+        self.publish(job_id, EventKind::Stage(RunStage::RunStarted));
+    }
+
+    fn release(&self, job_id: &str) {
+        let mut runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+        runs.remove(job_id);
+    }
+
+    /// Asks the run of a job to stop. Returns `false` if the job is not
+    /// running.
+    pub(crate) fn cancel(&self, job_id: &str) -> bool {
+        let runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+        match runs.get(job_id) {
+            Some(run) => {
+                run.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Asks every run to stop, when the server shuts down.
+    pub(crate) fn cancel_all(&self) {
+        let runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+        for run in runs.values() {
+            run.cancel.cancel();
+        }
+    }
+
+    /// Number of jobs running or waiting for a processing slot.
+    pub(crate) fn running_jobs(&self) -> usize {
+        let runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+        runs.len()
+    }
+
+    /// Sends an event to the subscribers of a job. A completed, failed or
+    /// cancelled stage ends the run and closes its streams.
+    pub(crate) fn publish(&self, job_id: &str, kind: EventKind) {
+        let mut runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+        let run = match runs.get_mut(job_id) {
+            Some(run) => run,
+            None => return,
+        };
+
+        match &kind {
+            EventKind::Progress(ProgressEvent::Started { total_files }) => {
+                run.total_files = *total_files;
+                run.done_files = 0;
+            }
+            EventKind::Progress(
+                ProgressEvent::FileFinished { .. }
+                | ProgressEvent::FileFailed { .. },
+            ) => run.done_files += 1,
+            _ => {}
+        }
+        let terminal = matches!(&kind, EventKind::Stage(s) if s.is_terminal());
+        let completed =
+            matches!(&kind, EventKind::Stage(RunStage::Completed { .. }));
+        let percent = if completed {
+            100
+        } else {
+            (run.done_files.min(run.total_files) * 100)
+                .checked_div(run.total_files)
+                .unwrap_or(0) as u8
+        };
+
+        let event = JobEvent {
+            job_id: job_id.to_string(),
+            version: run.version,
+            percent,
+            kind,
+        };
+        run.subscribers.retain(|subscriber| {
+            subscriber.unbounded_send(event.clone()).is_ok()
+        });
+        run.events.push(event);
+
+        if terminal {
+            runs.remove(job_id);
+        }
+    }
+
+    /// Subscribes to the run of a job, starting with the events it already
+    /// sent. Returns `None` if the job has no run in progress.
+    pub(crate) fn subscribe(
+        &self,
+        job_id: &str,
+    ) -> Option<UnboundedReceiver<JobEvent>> {
+        let mut runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+        let run = runs.get_mut(job_id)?;
+        let (sender, receiver) = unbounded();
+        for event in &run.events {
+            let _ = sender.unbounded_send(event.clone());
+        }
+        run.subscribers.push(sender);
+        Some(receiver)
+    }
+}
+
+/// A job marked as running by [`ProgressHub::reserve`]. Dropping it before
+/// its run is started frees the job again.
+pub(crate) struct Reservation<'a> {
+    hub: &'a ProgressHub,
+    job_id: String,
+    /// Flag set by [`ProgressHub::cancel`]
+    pub cancel: CancelFlag,
+    started: bool,
+}
+
+impl Reservation<'_> {
+    /// Starts the run of the job, which then ends with its completed,
+    /// failed or cancelled event.
+    pub(crate) fn start(mut self, version: u32) {
+        self.started = true;
+        self.hub.start(&self.job_id, version);
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.started {
+            self.hub.release(&self.job_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelling_a_queued_job_sets_its_flag() {
+        let hub = ProgressHub::default();
+        let reservation = hub.reserve("job").unwrap();
+        assert!(hub.reserve("job").is_none());
+
+        // A job waiting for a processing slot has not started yet
+        assert!(hub.cancel("job"));
+        assert!(reservation.cancel.is_cancelled());
+
+        drop(reservation);
+        assert!(!hub.cancel("job"));
+        assert!(hub.reserve("job").is_some());
+    }
+
+    #[test]
+    fn cancelling_a_running_job_lasts_until_it_ends() {
+        let hub = ProgressHub::default();
+        let reservation = hub.reserve("job").unwrap();
+        let cancel = reservation.cancel.clone();
+        reservation.start(1);
+        let mut events = hub.subscribe("job").unwrap();
+
+        assert!(hub.cancel("job"));
+        assert!(cancel.is_cancelled());
+        // The job stays reserved until its run reports how it ended
+        assert!(hub.reserve("job").is_none());
+        assert_eq!(hub.running_jobs(), 1);
+
+        hub.publish("job", EventKind::Stage(RunStage::Cancelled));
+        assert!(!hub.cancel("job"));
+        assert!(hub.reserve("job").is_some());
+
+        let names: Vec<_> =
+            std::iter::from_fn(|| events.try_next().ok().flatten())
+                .map(|event| event.name())
+                .collect();
+        assert_eq!(names, ["run_started", "cancelled"]);
+    }
+}
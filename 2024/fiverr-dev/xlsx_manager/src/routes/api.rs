@@ -1,36 +1,108 @@
+use crate::config::{AppConfig, UploadConfig};
+use crate::jobs::{
+    inputs_dir, run_dir, FailedFile, JobOptions, JobRecord, JobRun, JobStatus,
+    JobStore, INPUTS_DIR, INTERRUPTED_MESSAGE, UPLOAD_DIR,
+};
+use crate::limits::{JobSlots, RateLimiter, SlotError};
+use crate::metrics::Metrics;
+use crate::pool::ProcessingPool;
+use crate::profiles::ProfileStore;
+use crate::progress::{EventKind, ProgressHub, RunStage};
+use crate::routes::auth::Principal;
+use crate::routes::error::ApiError;
 use crate::routes::file_ops::{
-    create_guid_directory, list_excel_files_in_directory,
+    create_guid_directory, sanitize_filename, unique_file_path, FileKind,
+    MAGIC_LEN,
 };
+use crate::routes::profiles::{find_profile, profile_version_not_found};
 use crate::routes::request::{
-    NumberOfRowsToDeleteRequest, ZipFileDownloadRequest,
+    JobListQuery, NumberOfRowsToDeleteRequest, PreviewQuery,
+    ZipFileDownloadRequest,
+};
+use crate::routes::response::{
+    FileError, FilePreview, PreviewResponse, ProcessResponse,
 };
-use crate::routes::response::ProcessResponse;
-use crate::routes::zip_ops::{create_zip_from_folder, extract_zip_file};
-use crate::xlsx_manager::xlsx_manager::process_excel_files_parallel;
+use crate::shutdown::Shutdown;
 use actix_multipart::Multipart;
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
 use chrono::Utc;
 use futures::StreamExt;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use serde_json::json;
+use std::env;
 use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
 use std::time::Instant;
 use uuid::Uuid;
+use xlsx_manager::{
+    create_zip_from_folder, extract_zip_file_to, list_excel_files_in_directory,
+    preview_workbook, process_input_folder_with_progress, with_job_id,
+    CancelFlag, ProcessOptions,
+};
+
+/// Largest page size accepted by `GET /jobs`
+const MAX_JOBS_PER_PAGE: usize = 100;
+/// Most rows per sheet returned by `GET /jobs/{job_id}/preview`
+const MAX_PREVIEW_ROWS: usize = 500;
+/// Largest `num_rows_to_delete` of a preview, as those rows are read too
+const MAX_PREVIEW_DELETED_ROWS: usize = 10_000;
 
 pub(crate) fn configure_routes(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::resource("/upload").route(web::post().to(upload)))
-        .service(web::resource("/process").route(web::post().to(process)))
-        .service(
-            web::resource("/remove/{job_id}").route(web::delete().to(remove)),
-        )
-        .service(web::resource("/download").route(web::post().to(download)));
+    // Report malformed JSON bodies, path parameters and query strings with
+    // the same envelope as every other error
+    cfg.app_data(web::JsonConfig::default().error_handler(|err, _req| {
+        ApiError::bad_request("invalid_request_body", err.to_string()).into()
+    }))
+    .app_data(web::PathConfig::default().error_handler(|err, _req| {
+        ApiError::bad_request("invalid_path", err.to_string()).into()
+    }))
+    .app_data(web::QueryConfig::default().error_handler(|err, _req| {
+        ApiError::bad_request("invalid_query", err.to_string()).into()
+    }))
+    .service(web::resource("/upload").route(web::post().to(upload)))
+    .service(web::resource("/process").route(web::post().to(process)))
+    .service(web::resource("/remove/{job_id}").route(web::delete().to(remove)))
+    .service(web::resource("/download").route(web::post().to(download)))
+    .service(web::resource("/jobs").route(web::get().to(list_jobs)))
+    .service(web::resource("/jobs/{job_id}").route(web::get().to(get_job)))
+    .service(
+        web::resource("/jobs/{job_id}/preview")
+            .route(web::get().to(preview_job)),
+    )
+    .service(
+        web::resource("/jobs/{job_id}/cancel")
+            .route(web::post().to(cancel_job)),
+    )
+    .service(
+        web::resource("/jobs/{job_id}/events").route(web::get().to(job_events)),
+    )
+    .service(
+        web::resource("/jobs/{job_id}/runs/{version}")
+            .route(web::get().to(get_run)),
+    )
+    .service(
+        web::resource("/jobs/{job_id}/runs/{version}/download")
+            .route(web::get().to(download_run)),
+    );
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn upload(
     http_req: HttpRequest,
     mut payload: Multipart,
-) -> impl Responder {
+    config: web::Data<AppConfig>,
+    store: web::Data<JobStore>,
+    rate_limiter: web::Data<RateLimiter>,
+    metrics: web::Data<Metrics>,
+    shutdown: web::Data<Shutdown>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
+    check_accepting_jobs(&shutdown)?;
+    check_rate_limit(&http_req, &principal, &rate_limiter)?;
+
     // Log all headers in debug mode
     #[cfg(debug_assertions)]
     for (header_name, header_value) in http_req.headers() {
@@ -42,307 +114,1040 @@ async fn upload(
     info!("Generated GUID: {}", guid);
 
     // Create a folder using the GUID
-    let folder_path = match create_guid_directory(&guid) {
-        Ok(path) => path,
-        Err(e) => {
-            error!("Failed to create folder: {}", e);
-            return HttpResponse::InternalServerError()
-                .body("Failed to create upload folder");
+    let folder_path = create_guid_directory(&guid).map_err(|e| {
+        error!("Failed to create folder: {}", e);
+        ApiError::internal("io_error", "Failed to create upload folder")
+            .with_job_id(&guid)
+    })?;
+
+    // Save the files and the job metadata, discarding the whole job if any
+    // of them is rejected
+    let inputs_path = folder_path.join(INPUTS_DIR);
+    let saved = save_uploaded_files(
+        &mut payload,
+        &inputs_path,
+        &config.upload,
+        &metrics,
+    )
+    .await
+    .and_then(|input_files| {
+        let record = JobRecord {
+            owner: principal.name().map(str::to_string),
+            ..JobRecord::new(guid.clone(), input_files)
+        };
+        store.save(&record).map_err(job_store_error)
+    });
+    if let Err(err) = saved {
+        if let Err(e) = fs::remove_dir_all(&folder_path) {
+            error!("Failed to remove rejected upload folder: {}", e);
         }
-    };
+        return Err(err.with_job_id(&guid));
+    }
+
+    // Return the GUID as the response body
+    Ok(HttpResponse::Ok().body(guid))
+}
+
+// Writes each file of the multipart payload into `folder_path`, validating
+// its name, type and size against the upload limits. Returns the names the
+// files were saved under.
+async fn save_uploaded_files(
+    payload: &mut Multipart,
+    folder_path: &Path,
+    limits: &UploadConfig,
+    metrics: &Metrics,
+) -> Result<Vec<String>, ApiError> {
+    let mut request_size: u64 = 0;
+    let mut saved_files = Vec::new();
 
     // Process each field in the multipart payload
     while let Some(field_result) = payload.next().await {
         // Handle payload errors
-        let mut field = match field_result {
-            Ok(field) => field,
-            Err(e) => {
-                error!("Payload error: {}", e);
-                return HttpResponse::InternalServerError()
-                    .body(format!("Payload error: {}", e));
-            }
-        };
+        let mut field = field_result.map_err(|e| {
+            error!("Payload error: {}", e);
+            ApiError::bad_request(
+                "payload_error",
+                format!("Payload error: {}", e),
+            )
+        })?;
 
         // Check if content disposition is present
-        let content_disposition = match field.content_disposition() {
-            Some(disposition) => disposition,
-            None => {
+        let content_disposition =
+            field.content_disposition().ok_or_else(|| {
                 error!("Missing content disposition");
-                return HttpResponse::BadRequest()
-                    .body("Content disposition is missing");
-            }
-        };
+                ApiError::bad_request(
+                    "missing_content_disposition",
+                    "Content disposition is missing",
+                )
+            })?;
 
         // Extract the filename from content disposition
-        let filename = match content_disposition.get_filename() {
-            Some(name) => name,
-            None => {
+        let original_filename = content_disposition
+            .get_filename()
+            .ok_or_else(|| {
                 error!("Missing filename in content disposition");
-                return HttpResponse::BadRequest().body("Filename is missing");
-            }
-        };
+                ApiError::bad_request("missing_filename", "Filename is missing")
+            })?
+            .to_string();
 
-        let filepath = Path::new(&folder_path).join(&filename);
+        // Never trust the client's file name: strip paths and odd characters
+        let filename =
+            sanitize_filename(&original_filename).ok_or_else(|| {
+                error!("Rejected file name: {:?}", original_filename);
+                ApiError::bad_request("invalid_filename", "Invalid file name")
+                    .with_details(json!({ "file": original_filename }))
+            })?;
+
+        let file_kind =
+            FileKind::from_filename(&filename).ok_or_else(|| {
+                error!("Rejected file type: {}", filename);
+                ApiError::new(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "unsupported_file_type",
+                    "Only .xlsx, .xls and .zip files are accepted",
+                )
+                .with_details(json!({ "file": filename }))
+            })?;
+
+        let filepath = unique_file_path(folder_path, &filename);
         debug!("Saving file to: {:?}", filepath);
 
         // Create a file for writing
-        let mut file = match fs::File::create(&filepath) {
-            Ok(f) => f,
-            Err(e) => {
-                error!("File creation error: {}", e);
-                return HttpResponse::InternalServerError()
-                    .body(format!("File create error: {}", e));
-            }
-        };
+        let mut file = fs::File::create(&filepath).map_err(|e| {
+            error!("File creation error: {}", e);
+            ApiError::internal("io_error", format!("File create error: {}", e))
+                .with_details(json!({ "file": filename }))
+        })?;
 
-        // Write chunks into the file
+        // Write chunks into the file, enforcing the size limits
+        let mut file_size: u64 = 0;
+        let mut header = Vec::with_capacity(MAGIC_LEN);
         while let Some(chunk_result) = field.next().await {
-            let chunk = match chunk_result {
-                Ok(chunk) => chunk,
-                Err(e) => {
-                    error!("Chunk read error: {}", e);
-                    return HttpResponse::InternalServerError()
-                        .body(format!("File chunk error: {}", e));
-                }
-            };
+            let chunk = chunk_result.map_err(|e| {
+                error!("Chunk read error: {}", e);
+                ApiError::bad_request(
+                    "payload_error",
+                    format!("File chunk error: {}", e),
+                )
+                .with_details(json!({ "file": filename }))
+            })?;
 
-            if let Err(e) = file.write_all(&chunk) {
-                error!("File write error: {}", e);
-                return HttpResponse::InternalServerError()
-                    .body(format!("File write error: {}", e));
+            file_size += chunk.len() as u64;
+            request_size += chunk.len() as u64;
+            metrics.add_uploaded(chunk.len() as u64);
+            if file_size > limits.max_file_size {
+                error!("File {} exceeds the size limit", filename);
+                return Err(ApiError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "file_too_large",
+                    format!(
+                        "File is larger than the {} bytes limit",
+                        limits.max_file_size
+                    ),
+                )
+                .with_details(
+                    json!({ "file": filename, "limit": limits.max_file_size }),
+                ));
             }
+            if request_size > limits.max_request_size {
+                error!("Upload exceeds the request size limit");
+                return Err(ApiError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "request_too_large",
+                    format!(
+                        "Upload is larger than the {} bytes limit",
+                        limits.max_request_size
+                    ),
+                )
+                .with_details(json!({ "limit": limits.max_request_size })));
+            }
+
+            // Keep the first bytes to verify the file type
+            let missing = MAGIC_LEN - header.len();
+            header.extend_from_slice(&chunk[..missing.min(chunk.len())]);
+
+            file.write_all(&chunk).map_err(|e| {
+                error!("File write error: {}", e);
+                ApiError::internal(
+                    "io_error",
+                    format!("File write error: {}", e),
+                )
+                .with_details(json!({ "file": filename }))
+            })?;
+        }
+
+        // The content must match the format the extension promises
+        if !file_kind.matches(&header) {
+            error!("Content of {} does not match its extension", filename);
+            return Err(ApiError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_file_type",
+                "File content does not match its extension",
+            )
+            .with_details(json!({ "file": filename })));
         }
+
+        saved_files.push(
+            filepath
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or(filename),
+        );
     }
 
-    // Return the GUID as the response body
-    HttpResponse::Ok().body(guid)
+    if saved_files.is_empty() {
+        return Err(ApiError::bad_request(
+            "no_files",
+            "No files were uploaded",
+        ));
+    }
+
+    Ok(saved_files)
+}
+
+// Reports a failure to read or write the job metadata.
+fn job_store_error(err: std::io::Error) -> ApiError {
+    error!("Job metadata error: {}", err);
+    ApiError::internal("io_error", format!("Job metadata error: {}", err))
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn process(
+    http_req: HttpRequest,
     row_deletion_request: web::Json<NumberOfRowsToDeleteRequest>,
-) -> impl Responder {
+    config: web::Data<AppConfig>,
+    store: web::Data<JobStore>,
+    profiles: web::Data<ProfileStore>,
+    progress: web::Data<ProgressHub>,
+    rate_limiter: web::Data<RateLimiter>,
+    job_slots: web::Data<JobSlots>,
+    pool: web::Data<ProcessingPool>,
+    metrics: web::Data<Metrics>,
+    shutdown: web::Data<Shutdown>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
+    check_accepting_jobs(&shutdown)?;
+    check_rate_limit(&http_req, &principal, &rate_limiter)?;
     let start_time = Instant::now(); // Start timing
 
     let job_id = &row_deletion_request.job_id;
-    let rows_to_delete = row_deletion_request.num_rows_to_delete as usize;
+    let options = request_options(&row_deletion_request, &profiles, &principal)
+        .map_err(|err| err.with_job_id(job_id))?;
+    let rows_to_delete = options.num_rows_to_delete;
     info!(
-        "Received process request for job_id: {}, rows_to_delete: {}",
-        job_id, rows_to_delete
+        "Received process request for job_id: {}, options: {:?}",
+        job_id, options
     );
 
-    let job_folder = format!("upload/{}", job_id);
-    let job_folder_path = Path::new(&job_folder);
+    // Ensure the job exists and belongs to the caller
+    let job_folder_path = find_job_dir(&store, &principal, job_id)?;
 
-    // Ensure the job folder exists
-    if !job_folder_path.exists() {
-        let error_message = format!(
-            "Job folder for Job-Id '{}' not found in the 'upload' folder",
-            job_id
-        );
-        error!("{}", error_message);
-        return HttpResponse::NotFound().body(error_message);
-    }
+    let mut process_options = options
+        .process_options()
+        .map_err(|err| ApiError::from(err).with_job_id(job_id))?;
+
+    // Only one run of a job at a time, so it can be cancelled
+    let reservation =
+        progress.reserve(job_id).ok_or_else(|| job_running(job_id))?;
+    process_options.cancel = reservation.cancel.clone();
+    process_options.memory_budget = pool.memory_budget().clone();
+    process_options.zip_limits = config.upload.zip_limits();
+
+    // Wait for a free processing slot, held until the run ends
+    let slot = job_slots
+        .acquire()
+        .await
+        .map_err(|err| server_busy(&job_slots, err).with_job_id(job_id))?;
+    check_accepting_jobs(&shutdown).map_err(|err| err.with_job_id(job_id))?;
 
-    // Generate a timestamped output folder name
+    // Record the run before starting it, which assigns its version
+    let mut run = store
+        .update(job_id, |record| {
+            record.start_run(options);
+        })
+        .map_err(|err| job_store_error(err).with_job_id(job_id))?
+        .and_then(|record| record.runs.last().cloned())
+        .ok_or_else(|| {
+            ApiError::not_found("job_not_found", "Job was removed")
+                .with_job_id(job_id)
+        })?;
+    info!("Starting run {} of job {}", run.version, job_id);
+    reservation.start(run.version);
+
+    // Each run writes to its own folder, leaving the inputs untouched
     let timestamp = Utc::now().format("%m%d%y%H%M%S").to_string();
+    let run_dir_path = run_dir(&job_folder_path, run.version);
     let processed_output_dir_path =
-        job_folder_path.join(format!("firstsheet{}", timestamp));
-
-    // Create the output folder
-    if let Err(err) = fs::create_dir(&processed_output_dir_path) {
-        let error_message = format!(
-            "Failed to create output folder {}: {}",
-            processed_output_dir_path.display(),
-            err
-        );
-        error!("{}", error_message);
-        return HttpResponse::InternalServerError().body(error_message);
-    }
+        run_dir_path.join(format!("firstsheet{}", timestamp));
 
-    // Retrieve and unzip any ZIP files in the job folder (parallelize ZIP extraction)
-    let zip_files: Vec<_> = match fs::read_dir(&job_folder_path) {
-        Ok(entries) => entries
-            .filter_map(|entry| {
-                entry.ok().and_then(|dir_entry| {
-                    let path_buf = dir_entry.path();
-                    if path_buf.extension().and_then(|s| s.to_str())
-                        == Some("zip")
-                    {
-                        Some(path_buf)
-                    } else {
-                        None
-                    }
+    // Process the files in the processing pool, keeping this worker free.
+    // The run is finished there too, so that it is recorded even if the
+    // client goes away before it ends.
+    let pending_run = run.clone();
+    let finisher = RunFinisher {
+        job_id: job_id.clone(),
+        run_dir_path: run_dir_path.clone(),
+        cancel: process_options.cancel.clone(),
+        start_time,
+        store: store.clone(),
+        progress: progress.clone(),
+        shutdown: shutdown.clone(),
+    };
+    let work = {
+        let progress = progress.clone();
+        let metrics = metrics.clone();
+        let job_id = job_id.clone();
+        move || {
+            let _slot = slot;
+            let outcome = with_job_id(&job_id, || {
+                panic::catch_unwind(AssertUnwindSafe(|| {
+                    run_job(
+                        &job_folder_path,
+                        &run_dir_path,
+                        &processed_output_dir_path,
+                        &process_options,
+                        &mut run,
+                        &|kind| {
+                            if let EventKind::Progress(event) = &kind {
+                                metrics.record(event);
+                            }
+                            progress.publish(&job_id, kind)
+                        },
+                    )
+                }))
+                .unwrap_or_else(|_| {
+                    error!("Processing of job {} panicked", job_id);
+                    Err(processing_aborted())
                 })
-            })
-            .collect(),
-        Err(err) => {
-            let error_message = format!(
-                "Failed to read the directory '{}': {}",
-                job_folder_path.display(),
-                err
-            );
-            error!("{}", error_message);
-            return HttpResponse::InternalServerError().body(error_message);
+            });
+            let outcome = finisher.finish(&mut run, outcome);
+            (outcome, run)
         }
     };
-
-    // Unzip each found ZIP file in parallel (if there are any)
-    zip_files.iter().for_each(|zip_file_path| {
-        if let Err(err) = extract_zip_file(zip_file_path) {
-            error!(
-                "Failed to unzip and extract '{}': {}",
-                zip_file_path.display(),
-                err
-            );
-        }
+    let (outcome, run) = pool.run(work).await.unwrap_or_else(|_| {
+        error!("Recording the end of job {} panicked", job_id);
+        (Err(processing_aborted()), pending_run)
     });
+    outcome.map_err(|err| err.with_job_id(job_id))?;
 
-    // Process Excel files in the job folder
-    let (num_rows_deleted, processed_zip_file_name) =
-        match list_excel_files_in_directory(&job_folder_path) {
-            Ok(excel_files) => {
-                if !excel_files.is_empty() {
-                    info!(
-                        "Found {} Excel files to process in '{}'",
-                        excel_files.len(),
-                        processed_output_dir_path.display()
-                    );
+    // Create response
+    let response = ProcessResponse {
+        job_id: job_id.to_string(),
+        version: run.version,
+        time_taken: run.time_taken.unwrap_or_default(),
+        num_rows_deleted: rows_to_delete,
+        rows_deleted: run.rows_deleted,
+        zip_file_name: run.zip_file_name.unwrap_or_default(), // Handle None case
+        failed_files: run.failed_files,
+    };
 
-                    // Process the Excel files in parallel, deleting rows
-                    if let Err(err) = process_excel_files_parallel(
-                        job_folder_path.to_str().unwrap(), // Source folder (unzipped files)
-                        processed_output_dir_path.to_str().unwrap(), // Output folder (for processed files)
-                        &excel_files,   // List of Excel files
-                        rows_to_delete, // Number of rows to delete
-                    ) {
-                        let error_message = format!(
-                            "Error reading Excel files in '{}': {}",
-                            processed_output_dir_path.display(),
-                            err
-                        );
-                        error!("{}", error_message);
-                        return HttpResponse::InternalServerError()
-                            .body(error_message);
-                    }
-
-                    info!(
-                        "Successfully processed {} Excel files",
-                        excel_files.len()
-                    );
+    Ok(HttpResponse::Ok().json(response))
+}
 
-                    // Create a zip from the output folder
-                    let zip_name = match create_zip_from_folder(
-                        &processed_output_dir_path,
-                    ) {
-                        Ok(path_buf) => {
-                            Some(path_buf.to_string_lossy().into_owned())
-                        }
-                        Err(err) => {
-                            error!("Failed to create ZIP file: {}", err);
-                            None
-                        }
-                    };
-
-                    (rows_to_delete, zip_name)
-                } else {
-                    let error_message = format!(
-                        "No Excel files found in folder {}",
-                        processed_output_dir_path.display()
-                    );
-                    info!("{}", error_message);
-                    return HttpResponse::NotFound().body(error_message);
+// What a run needs to record how it ended, from the processing pool.
+struct RunFinisher {
+    job_id: String,
+    run_dir_path: PathBuf,
+    cancel: CancelFlag,
+    start_time: Instant,
+    store: web::Data<JobStore>,
+    progress: web::Data<ProgressHub>,
+    shutdown: web::Data<Shutdown>,
+}
+
+impl RunFinisher {
+    // Records how the run ended in the job metadata and publishes its last
+    // event, which frees the job. Runs cancelled by a shutdown fail, as
+    // they were not cancelled by the user.
+    fn finish(
+        &self,
+        run: &mut JobRun,
+        outcome: Result<(), ApiError>,
+    ) -> Result<(), ApiError> {
+        let job_id = &self.job_id;
+        let time_taken = format!("{:.2?}", self.start_time.elapsed());
+        info!("Processing time: {}", time_taken);
+        run.finished_at = Some(Utc::now());
+        run.time_taken = Some(time_taken);
+
+        let interrupted =
+            self.cancel.is_cancelled() && self.shutdown.is_interrupting();
+        let outcome = match outcome {
+            Err(_) if interrupted => Err(shutting_down()),
+            outcome => outcome,
+        };
+        let stage = match &outcome {
+            Ok(()) => {
+                run.status = JobStatus::Completed;
+                RunStage::Completed {
+                    zip_file_name: run
+                        .zip_file_name
+                        .clone()
+                        .unwrap_or_default(),
                 }
             }
+            Err(_) if self.cancel.is_cancelled() && !interrupted => {
+                info!("Run {} of job {} was cancelled", run.version, job_id);
+                discard_outputs(run, &self.run_dir_path);
+                run.status = JobStatus::Cancelled;
+                RunStage::Cancelled
+            }
             Err(err) => {
-                let error_message = format!(
-                    "Error reading Excel files in '{}': {}",
-                    processed_output_dir_path.display(),
-                    err
-                );
-                error!("{}", error_message);
-                return HttpResponse::InternalServerError().body(error_message);
+                if interrupted {
+                    warn!(
+                        "Run {} of job {} was interrupted",
+                        run.version, job_id
+                    );
+                    discard_outputs(run, &self.run_dir_path);
+                    run.error = Some(INTERRUPTED_MESSAGE.to_string());
+                } else {
+                    run.error = Some(err.to_string());
+                }
+                run.status = JobStatus::Failed;
+                RunStage::Failed {
+                    code: err.code().to_string(),
+                    message: err.message().to_string(),
+                }
             }
         };
+        if let Err(err) =
+            self.store.update(job_id, |record| record.record_run(run.clone()))
+        {
+            job_store_error(err);
+        }
+        self.progress.publish(job_id, EventKind::Stage(stage));
+        outcome
+    }
+}
 
-    // Calculate and log the time taken
-    let elapsed_time = start_time.elapsed();
-    let time_taken = format!("{:.2?}", elapsed_time);
-    info!("Processing time: {:.2?}", time_taken);
+// Removes the partial outputs of a run that did not complete.
+fn discard_outputs(run: &mut JobRun, run_dir_path: &Path) {
+    if let Err(err) = fs::remove_dir_all(run_dir_path) {
+        warn!("Failed to remove '{}': {}", run_dir_path.display(), err);
+    }
+    run.output_files.clear();
+    run.rows_deleted.clear();
+    run.failed_files.clear();
+    run.zip_file_name = None;
+}
 
-    // Create response
-    let response = ProcessResponse {
-        job_id: job_id.to_string(),
-        time_taken,
-        num_rows_deleted,
-        zip_file_name: processed_zip_file_name
-            .unwrap_or_else(|| "".to_string()), // Handle None case
+fn processing_aborted() -> ApiError {
+    ApiError::internal("processing_aborted", "Processing stopped unexpectedly")
+}
+
+// Counts a request against the rate limit of its client: the principal when
+// authentication is enabled, the peer address otherwise.
+fn check_rate_limit(
+    req: &HttpRequest,
+    principal: &Principal,
+    rate_limiter: &RateLimiter,
+) -> Result<(), ApiError> {
+    let client = match principal.name() {
+        Some(name) => format!("principal:{}", name),
+        None => req
+            .peer_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_default(),
+    };
+    rate_limiter.check(&client).map_err(|delay| {
+        warn!("Rate limit exceeded by {}", client);
+        ApiError::rate_limited(delay)
+    })
+}
+
+// Reports a job that could not get a processing slot.
+fn server_busy(job_slots: &JobSlots, err: SlotError) -> ApiError {
+    let message = match err {
+        SlotError::QueueFull => "Too many jobs are waiting to be processed",
+        SlotError::Timeout => "No processing slot became free in time",
     };
+    warn!("{}", message);
+    ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "server_busy", message)
+        .with_retry_after(job_slots.retry_after())
+}
+
+// Turns new jobs away once the server is shutting down.
+fn check_accepting_jobs(shutdown: &Shutdown) -> Result<(), ApiError> {
+    if shutdown.is_draining() {
+        return Err(shutting_down());
+    }
+    Ok(())
+}
+
+fn shutting_down() -> ApiError {
+    ApiError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "shutting_down",
+        "The server is shutting down",
+    )
+}
 
-    HttpResponse::Ok().json(response)
+// Reports a request that needs the job not to be running.
+fn job_running(job_id: &str) -> ApiError {
+    ApiError::new(
+        StatusCode::CONFLICT,
+        "job_running",
+        format!("Job-Id {} is being processed; cancel it first", job_id),
+    )
+    .with_job_id(job_id)
 }
 
-async fn remove(job_id: web::Path<String>) -> impl Responder {
+// Returns the options of a `/process` request, taken from the saved profile
+// it names, if any.
+fn request_options(
+    request: &NumberOfRowsToDeleteRequest,
+    profiles: &ProfileStore,
+    principal: &Principal,
+) -> Result<JobOptions, ApiError> {
+    let name = match &request.profile {
+        Some(name) => name,
+        None => {
+            return Ok(JobOptions {
+                num_rows_to_delete: request.num_rows_to_delete as usize,
+                auto_detect_rows: request.auto_detect_rows,
+                overrides: request.overrides.clone(),
+                profile: None,
+                profile_version: None,
+            })
+        }
+    };
+
+    let profile = find_profile(profiles, principal, name)?;
+    let version =
+        profile.version(request.profile_version).ok_or_else(|| {
+            profile_version_not_found(
+                name,
+                request.profile_version.unwrap_or(0),
+            )
+        })?;
+    Ok(JobOptions {
+        profile: Some(profile.name.clone()),
+        profile_version: Some(version.version),
+        ..version.options.clone()
+    })
+}
+
+// Processes the inputs of a job into `processed_output_dir_path` and zips
+// the result, recording outputs and failed files in `run`. ZIP files are
+// extracted into a scratch folder of the run, removed afterwards. Errors
+// name files relative to the job folder.
+fn run_job(
+    job_dir_path: &Path,
+    run_dir_path: &Path,
+    processed_output_dir_path: &Path,
+    options: &ProcessOptions,
+    run: &mut JobRun,
+    report: &(dyn Fn(EventKind) + Sync),
+) -> Result<(), ApiError> {
+    // Create the output folder
+    fs::create_dir_all(processed_output_dir_path).map_err(|err| {
+        error!(
+            "Failed to create output folder {}: {}",
+            processed_output_dir_path.display(),
+            err
+        );
+        ApiError::internal(
+            "io_error",
+            format!("Failed to create the output folder: {}", err),
+        )
+    })?;
+
+    // Process the Excel files of the inputs and of their ZIP files
+    let inputs_dir_path = &inputs_dir(job_dir_path);
+    let extracted_dir_path = run_dir_path.join("extracted");
+    let result = process_input_folder_with_progress(
+        inputs_dir_path,
+        &extracted_dir_path,
+        processed_output_dir_path,
+        options,
+        &|event| report(EventKind::Progress(event)),
+    );
+    if let Err(err) = fs::remove_dir_all(&extracted_dir_path) {
+        warn!("Failed to remove '{}': {}", extracted_dir_path.display(), err);
+    }
+    let summary = result.map_err(|err| {
+        error!(
+            "Error processing Excel files in '{}': {}",
+            inputs_dir_path.display(),
+            err
+        );
+        ApiError::job_error(err, job_dir_path)
+    })?;
+
+    info!(
+        "Successfully processed {} of {} Excel files",
+        summary.processed.len(),
+        summary.processed.len() + summary.failed.len()
+    );
+    run.output_files = summary.processed;
+    run.rows_deleted = summary.rows_deleted;
+    run.failed_files = summary
+        .failed
+        .iter()
+        .map(|failure| FailedFile {
+            file: failure.file.clone(),
+            code: failure.error.code().to_string(),
+            message: failure.error.message_relative_to(job_dir_path),
+        })
+        .collect();
+
+    // Create a zip from the output folder
+    report(EventKind::Stage(RunStage::Zipping));
+    run.zip_file_name = match create_zip_from_folder(processed_output_dir_path)
+    {
+        Ok(path_buf) => Some(path_buf.to_string_lossy().into_owned()),
+        Err(err) => {
+            error!("Failed to create ZIP file: {}", err);
+            None
+        }
+    };
+
+    Ok(())
+}
+
+// Asks the running job to stop. The `/process` request running it removes
+// the partial outputs and records the run as cancelled.
+async fn cancel_job(
+    job_id: web::Path<String>,
+    store: web::Data<JobStore>,
+    progress: web::Data<ProgressHub>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
+    let job_id = job_id.into_inner();
+    find_job(&store, &principal, &job_id)?;
+    if !progress.cancel(&job_id) {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "job_not_running",
+            format!("Job-Id {} has no run in progress", job_id),
+        )
+        .with_job_id(job_id));
+    }
+
+    info!("Cancelling job {}", job_id);
+    Ok(HttpResponse::Accepted()
+        .json(json!({ "job_id": job_id, "status": "cancelling" })))
+}
+
+async fn remove(
+    job_id: web::Path<String>,
+    store: web::Data<JobStore>,
+    progress: web::Data<ProgressHub>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
     let job_id = job_id.into_inner();
 
     info!("Received delete request for job_id: {}", job_id);
+    let job_folder_path = find_job_dir(&store, &principal, &job_id)?;
+
+    // Deleting the folder under a running job would break it. Holding the
+    // job's reservation keeps a run from starting until it is gone.
+    let _reservation =
+        progress.reserve(&job_id).ok_or_else(|| job_running(&job_id))?;
+
+    // Log the folder existence
+    debug!("Folder for job_id {} exists", &job_id);
+
+    // Delete the folder and its contents
+    fs::remove_dir_all(&job_folder_path).map_err(|err| {
+        let error_message = format!("Failed to delete folder: {}", err);
+        error!("Err: {}", error_message);
+        ApiError::internal("io_error", error_message).with_job_id(&job_id)
+    })?;
+
+    // Folder deleted successfully
+    let response_message =
+        format!("Folder for Job-Id {} deleted successfully", job_id);
+    info!("Ok: {}", &response_message);
+    Ok(HttpResponse::Ok().body(response_message))
+}
+
+async fn list_jobs(
+    query: web::Query<JobListQuery>,
+    store: web::Data<JobStore>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
+    let query = query.into_inner();
+    if query.page == 0 || !(1..=MAX_JOBS_PER_PAGE).contains(&query.per_page) {
+        return Err(ApiError::bad_request(
+            "invalid_query",
+            format!(
+                "page must be at least 1 and per_page between 1 and {}",
+                MAX_JOBS_PER_PAGE
+            ),
+        ));
+    }
+
+    let jobs = store
+        .list()
+        .map_err(job_store_error)?
+        .into_iter()
+        .filter(|job| principal.can_access(job.owner.as_deref()))
+        .collect();
+    Ok(HttpResponse::Ok().json(query.page_of(jobs)))
+}
+
+async fn get_job(
+    job_id: web::Path<String>,
+    store: web::Data<JobStore>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
+    let record = find_job(&store, &principal, &job_id)?;
+    Ok(HttpResponse::Ok().json(record))
+}
+
+// Streams the progress of the running job as server-sent events, ending
+// with its `completed` or `failed` event. A job that is not running gets a
+// single `status` event.
+async fn job_events(
+    job_id: web::Path<String>,
+    store: web::Data<JobStore>,
+    progress: web::Data<ProgressHub>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
+    let job_id = job_id.into_inner();
+    let record = find_job(&store, &principal, &job_id)?;
 
-    let job_folder = format!("upload/{}", job_id);
-    let job_folder_path = Path::new(&job_folder);
-
-    if job_folder_path.exists() {
-        // Log the folder existence
-        debug!("Folder for job_id {} exists", &job_id);
-
-        // Delete the folder and its contents
-        match fs::remove_dir_all(&job_folder_path) {
-            Ok(_) => {
-                // Folder deleted successfully
-                let response_message = format!(
-                    "Folder for Job-Id {} deleted successfully",
-                    job_id
-                );
-                info!("Ok: {}", &response_message);
-                HttpResponse::Ok().body(response_message)
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"));
+    match progress.subscribe(&job_id) {
+        Some(events) => Ok(response.streaming(events.map(|event| {
+            Ok::<_, actix_web::Error>(web::Bytes::from(event.to_sse()))
+        }))),
+        None => {
+            let status = json!({
+                "job_id": record.job_id,
+                "status": record.status,
+                "version": record.runs.last().map(|run| run.version),
+            });
+            Ok(response.body(format!("event: status\ndata: {}\n\n", status)))
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn preview_job(
+    http_req: HttpRequest,
+    job_id: web::Path<String>,
+    query: web::Query<PreviewQuery>,
+    config: web::Data<AppConfig>,
+    store: web::Data<JobStore>,
+    pool: web::Data<ProcessingPool>,
+    rate_limiter: web::Data<RateLimiter>,
+    job_slots: web::Data<JobSlots>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
+    check_rate_limit(&http_req, &principal, &rate_limiter)?;
+    let job_id = job_id.into_inner();
+    let query = query.into_inner();
+    if !(1..=MAX_PREVIEW_ROWS).contains(&query.rows) {
+        return Err(ApiError::bad_request(
+            "invalid_query",
+            format!("rows must be between 1 and {}", MAX_PREVIEW_ROWS),
+        ));
+    }
+    if query.num_rows_to_delete > MAX_PREVIEW_DELETED_ROWS {
+        return Err(ApiError::bad_request(
+            "invalid_query",
+            format!(
+                "num_rows_to_delete must be at most {}",
+                MAX_PREVIEW_DELETED_ROWS
+            ),
+        ));
+    }
+
+    let job_folder_path = find_job_dir(&store, &principal, &job_id)?;
+
+    // Previews share the processing slots and memory budget with the runs
+    let slot = job_slots
+        .acquire()
+        .await
+        .map_err(|err| server_busy(&job_slots, err).with_job_id(&job_id))?;
+
+    // Zip files are extracted into a scratch folder, leaving the inputs
+    // untouched
+    let scratch_dir = env::temp_dir()
+        .join(format!("xlsx_manager-preview-{}", Uuid::new_v4()));
+    let options = ProcessOptions {
+        delete_first_n_rows: query.num_rows_to_delete,
+        detect_header_rows: query.auto_detect_rows,
+        memory_budget: pool.memory_budget().clone(),
+        zip_limits: config.upload.zip_limits(),
+        ..Default::default()
+    };
+    let rows = query.rows;
+    let preview_job_id = job_id.clone();
+    let files = pool
+        .run(move || {
+            let _slot = slot;
+            let files = with_job_id(&preview_job_id, || {
+                preview_inputs(
+                    &inputs_dir(&job_folder_path),
+                    &scratch_dir,
+                    &options,
+                    rows,
+                )
+            });
+            if scratch_dir.exists() {
+                if let Err(err) = fs::remove_dir_all(&scratch_dir) {
+                    warn!(
+                        "Failed to remove '{}': {}",
+                        scratch_dir.display(),
+                        err
+                    );
+                }
             }
-            Err(err) => {
-                // Error deleting folder
-                let error_message = format!("Failed to delete folder: {}", err);
-                error!("Err: {}", error_message);
-                HttpResponse::InternalServerError().body(error_message)
+            files
+        })
+        .await
+        .map_err(|_| processing_aborted().with_job_id(&job_id))?
+        .map_err(|err| {
+            ApiError::internal(
+                "io_error",
+                format!("Failed to read inputs: {}", err),
+            )
+            .with_job_id(&job_id)
+        })?;
+
+    Ok(HttpResponse::Ok().json(PreviewResponse {
+        job_id,
+        num_rows_to_delete: query.num_rows_to_delete,
+        auto_detect_rows: query.auto_detect_rows,
+        rows: query.rows,
+        files,
+    }))
+}
+
+// Previews every Excel file of the inputs folder and of its Zip files.
+fn preview_inputs(
+    inputs_dir_path: &Path,
+    scratch_dir: &Path,
+    options: &ProcessOptions,
+    max_rows: usize,
+) -> std::io::Result<Vec<FilePreview>> {
+    let mut entries: Vec<_> = fs::read_dir(inputs_dir_path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    entries.sort();
+
+    let mut files = Vec::new();
+    for path in entries {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match path.extension().and_then(|s| s.to_str()) {
+            Some("xlsx") | Some("xls") => {
+                files.push(file_preview(
+                    name,
+                    inputs_dir_path,
+                    &path,
+                    options,
+                    max_rows,
+                ));
+            }
+            Some("zip") => {
+                let extract_dir = scratch_dir.join(&name);
+                if let Err(err) = extract_zip_file_to(
+                    &path,
+                    &extract_dir,
+                    &options.zip_limits,
+                ) {
+                    error!("Failed to extract '{}': {}", path.display(), err);
+                    files.push(FilePreview {
+                        file: name,
+                        preview: None,
+                        error: Some(FileError {
+                            code: err.code().to_string(),
+                            message: err.message_relative_to(inputs_dir_path),
+                        }),
+                    });
+                    continue;
+                }
+                let mut members = list_excel_files_in_directory(&extract_dir)
+                    .unwrap_or_default();
+                members.sort();
+                for member in members {
+                    files.push(file_preview(
+                        format!("{}/{}", name, member),
+                        scratch_dir,
+                        &extract_dir.join(&member),
+                        options,
+                        max_rows,
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(files)
+}
+
+// Previews one workbook. Errors name it relative to `base`, the way `file`
+// does.
+fn file_preview(
+    file: String,
+    base: &Path,
+    path: &Path,
+    options: &ProcessOptions,
+    max_rows: usize,
+) -> FilePreview {
+    match preview_workbook(path, options, max_rows) {
+        Ok(preview) => {
+            FilePreview { file, preview: Some(preview), error: None }
+        }
+        Err(err) => {
+            error!("Failed to preview '{}': {}", path.display(), err);
+            FilePreview {
+                file,
+                preview: None,
+                error: Some(FileError {
+                    code: err.code().to_string(),
+                    message: err.message_relative_to(base),
+                }),
             }
         }
-    } else {
-        // Folder doesn't exist, return an error
-        let error_message =
-            format!("Folder for Job-Id {} not found in upload folder", job_id);
-        debug!("NotFound: {}", error_message);
-        HttpResponse::NotFound().body(error_message)
     }
 }
 
+async fn get_run(
+    path: web::Path<(String, u32)>,
+    store: web::Data<JobStore>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
+    let (job_id, version) = path.into_inner();
+    let run = find_run(&store, &principal, &job_id, version)?;
+    Ok(HttpResponse::Ok().json(run))
+}
+
+async fn download_run(
+    path: web::Path<(String, u32)>,
+    store: web::Data<JobStore>,
+    metrics: web::Data<Metrics>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
+    let (job_id, version) = path.into_inner();
+    let run = find_run(&store, &principal, &job_id, version)?;
+    let zip_file_name = run.zip_file_name.ok_or_else(|| {
+        ApiError::not_found(
+            "file_not_found",
+            format!("Run {} has no output to download", version),
+        )
+        .with_job_id(&job_id)
+    })?;
+
+    let file_content = fs::read(&zip_file_name).map_err(|err| {
+        error!("Failed to read '{}': {}", zip_file_name, err);
+        ApiError::not_found("file_not_found", "Run output no longer exists")
+            .with_job_id(&job_id)
+    })?;
+
+    metrics.add_downloaded(file_content.len() as u64);
+    let file_name = format!("{}-v{}.zip", job_id, version);
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", file_name),
+        ))
+        .body(file_content))
+}
+
+// Loads the metadata of a job of the caller. Jobs of other callers are
+// reported as not found, like jobs that do not exist.
+fn find_job(
+    store: &JobStore,
+    principal: &Principal,
+    job_id: &str,
+) -> Result<JobRecord, ApiError> {
+    store
+        .get(job_id)
+        .map_err(|err| job_store_error(err).with_job_id(job_id))?
+        .filter(|record| principal.can_access(record.owner.as_deref()))
+        .ok_or_else(|| {
+            ApiError::not_found(
+                "job_not_found",
+                format!("Job-Id {} not found", job_id),
+            )
+            .with_job_id(job_id)
+        })
+}
+
+// Returns the folder of a job of the caller.
+fn find_job_dir(
+    store: &JobStore,
+    principal: &Principal,
+    job_id: &str,
+) -> Result<PathBuf, ApiError> {
+    find_job(store, principal, job_id)?;
+    Ok(store.job_dir(job_id).expect("job ids of stored jobs are valid"))
+}
+
+// Loads one run of a job, reporting a missing job or run as not found.
+fn find_run(
+    store: &JobStore,
+    principal: &Principal,
+    job_id: &str,
+    version: u32,
+) -> Result<JobRun, ApiError> {
+    let record = find_job(store, principal, job_id)?;
+    record.run(version).cloned().ok_or_else(|| {
+        ApiError::not_found(
+            "run_not_found",
+            format!("Job-Id {} has no run {}", job_id, version),
+        )
+        .with_job_id(job_id)
+    })
+}
+
 async fn download(
     zip_file_url: web::Json<ZipFileDownloadRequest>,
-) -> impl Responder {
+    store: web::Data<JobStore>,
+    metrics: web::Data<Metrics>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
     let zip_file_url_path = &zip_file_url.file_url;
     info!("Received download request: {}", zip_file_url_path);
 
+    // Only files of the caller's jobs can be downloaded
+    let owned = job_id_of_file(Path::new(zip_file_url_path))
+        .is_some_and(|job_id| find_job(&store, &principal, job_id).is_ok());
+
     // Check if the file exists
-    if let Ok(metadata) = fs::metadata(&zip_file_url_path) {
-        if metadata.is_file() {
-            // If the file exists and is valid, serve it
-            return match fs::read(&zip_file_url_path) {
-                Ok(file_content) => HttpResponse::Ok()
-                    .content_type("application/zip")
-                    .body(file_content),
-                Err(err) => {
-                    error!("Failed to read the file: {}", err);
-                    HttpResponse::InternalServerError()
-                        .body("Failed to read the file")
-                }
-            };
-        }
+    let is_file = fs::metadata(zip_file_url_path)
+        .map(|metadata| metadata.is_file())
+        .unwrap_or(false);
+    if !owned || !is_file {
+        // If the file doesn't exist, return a 404
+        return Err(ApiError::not_found("file_not_found", "File not found")
+            .with_details(json!({ "file_url": zip_file_url_path })));
     }
 
-    // If the file doesn't exist, return a 404
-    HttpResponse::NotFound().body("File not found")
+    // If the file exists and is valid, serve it
+    let file_content = fs::read(zip_file_url_path).map_err(|err| {
+        error!("Failed to read the file: {}", err);
+        ApiError::internal("io_error", "Failed to read the file")
+            .with_details(json!({ "file_url": zip_file_url_path }))
+    })?;
+
+    metrics.add_downloaded(file_content.len() as u64);
+    Ok(HttpResponse::Ok().content_type("application/zip").body(file_content))
+}
+
+// Returns the job a file of the upload folder belongs to, given a path such
+// as `upload/{job_id}/runs/1/firstsheet{timestamp}.zip`. Paths outside the
+// upload folder have none.
+fn job_id_of_file(path: &Path) -> Option<&str> {
+    let mut components = path.components();
+    if components.next()? != Component::Normal(UPLOAD_DIR.as_ref()) {
+        return None;
+    }
+    let job_id = components.next()?.as_os_str().to_str()?;
+    if !components.all(|component| matches!(component, Component::Normal(_))) {
+        return None;
+    }
+    Some(job_id)
 }
@@ -0,0 +1,154 @@
+use crate::config::{ApiKeyConfig, AppConfig};
+use crate::limits::RateLimiter;
+use crate::routes::error::ApiError;
+use actix_web::dev::Payload;
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, FromRequest, HttpRequest};
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::future::{ready, Ready};
+use subtle::ConstantTimeEq;
+
+/// Header carrying an API key, as an alternative to `Authorization: Bearer`
+const API_KEY_HEADER: &str = "X-API-Key";
+
+/// The caller of a request. With authentication enabled it is the principal
+/// whose key the request carries, and requests without a valid key are
+/// rejected with 401. Those rejections count against the rate limit of the
+/// caller's address, which gets 429 without its key being checked once over
+/// it.
+#[derive(Debug, Clone)]
+pub(crate) struct Principal(Option<String>);
+
+impl Principal {
+    /// Name of the caller, `None` when authentication is disabled.
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+
+    /// Checks whether the caller may access a job owned by `owner`. Without
+    /// authentication every job is accessible; with it, only the caller's.
+    pub(crate) fn can_access(&self, owner: Option<&str>) -> bool {
+        match &self.0 {
+            Some(name) => owner == Some(name.as_str()),
+            None => true,
+        }
+    }
+}
+
+impl FromRequest for Principal {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, ApiError>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(authenticate(req))
+    }
+}
+
+fn authenticate(req: &HttpRequest) -> Result<Principal, ApiError> {
+    let auth = match req.app_data::<web::Data<AppConfig>>() {
+        Some(config) if config.auth.enabled => &config.auth,
+        _ => return Ok(Principal(None)),
+    };
+    // Failed attempts are limited per address, so keys cannot be guessed at
+    // the speed of the network
+    let rate_limiter = req.app_data::<web::Data<RateLimiter>>();
+    let client = format!(
+        "auth:{}",
+        req.peer_addr().map(|addr| addr.ip().to_string()).unwrap_or_default()
+    );
+    if let Some(delay) = rate_limiter.and_then(|l| l.wait_time(&client)) {
+        warn!("Too many failed authentications from {}", client);
+        return Err(ApiError::rate_limited(delay));
+    }
+
+    let keys = request_keys(req);
+    let principal = if keys.is_empty() {
+        Err(unauthorized("Missing API key or bearer token"))
+    } else {
+        find_key(&auth.keys, &keys)
+            .map(|api_key| Principal(Some(api_key.principal.clone())))
+            .ok_or_else(|| unauthorized("Invalid API key or bearer token"))
+    };
+    if principal.is_err() {
+        if let Some(rate_limiter) = rate_limiter {
+            let _ = rate_limiter.check(&client);
+        }
+    }
+    principal
+}
+
+// Reads the keys of `X-API-Key` and of an `Authorization: Bearer` header.
+// Either may be wrong as long as the other one is valid.
+fn request_keys(req: &HttpRequest) -> Vec<&str> {
+    let headers = req.headers();
+    let api_key =
+        headers.get(API_KEY_HEADER).and_then(|value| value.to_str().ok());
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    [api_key, bearer]
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .collect()
+}
+
+// Returns the configured key matching one of `keys`. Every pair is compared,
+// so the time taken does not tell which matched.
+fn find_key<'a>(
+    api_keys: &'a [ApiKeyConfig],
+    keys: &[&str],
+) -> Option<&'a ApiKeyConfig> {
+    api_keys.iter().fold(None, |found, api_key| {
+        let matches = keys.iter().fold(false, |matches, key| {
+            matches | keys_match(&api_key.key, key)
+        });
+        found.or(matches.then_some(api_key))
+    })
+}
+
+// Compares two keys in a time that depends neither on where they differ nor
+// on their lengths, by comparing fixed-length digests of them.
+fn keys_match(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.as_slice().ct_eq(b.as_slice()).into()
+}
+
+fn unauthorized(message: &str) -> ApiError {
+    ApiError::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_match_only_identical_keys() {
+        assert!(keys_match("secret-key", "secret-key"));
+        assert!(!keys_match("secret-key", "secret-kez"));
+        assert!(!keys_match("secret-key", "secret-key-2"));
+        assert!(!keys_match("secret-key", "secret"));
+        assert!(!keys_match("secret-key", ""));
+        assert!(keys_match("", ""));
+    }
+
+    #[test]
+    fn find_key_accepts_any_valid_key() {
+        let api_keys = [
+            ApiKeyConfig { principal: "alice".into(), key: "a-key".into() },
+            ApiKeyConfig { principal: "bob".into(), key: "b-key".into() },
+        ];
+        let principal = |keys: &[&str]| {
+            find_key(&api_keys, keys).map(|api_key| api_key.principal.as_str())
+        };
+        assert_eq!(principal(&["b-key"]), Some("bob"));
+        // A wrong `X-API-Key` does not hide a valid bearer token
+        assert_eq!(principal(&["wrong", "a-key"]), Some("alice"));
+        assert_eq!(principal(&["a-key", "wrong"]), Some("alice"));
+        assert_eq!(principal(&["wrong", "also-wrong"]), None);
+        assert_eq!(principal(&[]), None);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::routes::response::ErrorResponse;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::Duration;
+use xlsx_manager::Error;
+
+/// Error returned by the route handlers, rendered as a JSON
+/// [`ErrorResponse`] envelope.
+#[derive(Debug)]
+pub(crate) struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    job_id: Option<String>,
+    details: Option<serde_json::Value>,
+    // Seconds to wait before retrying, sent as `Retry-After`
+    retry_after: Option<u64>,
+}
+
+impl ApiError {
+    pub(crate) fn new(
+        status: StatusCode,
+        code: &'static str,
+        message: impl Into<String>,
+    ) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            job_id: None,
+            details: None,
+            retry_after: None,
+        }
+    }
+
+    pub(crate) fn bad_request(
+        code: &'static str,
+        message: impl Into<String>,
+    ) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    pub(crate) fn not_found(
+        code: &'static str,
+        message: impl Into<String>,
+    ) -> Self {
+        Self::new(StatusCode::NOT_FOUND, code, message)
+    }
+
+    /// Rejects a client over its rate limit, telling it when to retry.
+    pub(crate) fn rate_limited(delay: Duration) -> Self {
+        Self::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "Too many requests, retry later",
+        )
+        .with_retry_after(delay)
+    }
+
+    pub(crate) fn internal(
+        code: &'static str,
+        message: impl Into<String>,
+    ) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, code, message)
+    }
+
+    /// Attaches the job the error relates to.
+    pub(crate) fn with_job_id(mut self, job_id: impl Into<String>) -> Self {
+        self.job_id = Some(job_id.into());
+        self
+    }
+
+    /// Tells the client when to retry, rounded up to whole seconds.
+    pub(crate) fn with_retry_after(mut self, delay: Duration) -> Self {
+        let seconds = delay.as_secs() + u64::from(delay.subsec_nanos() > 0);
+        self.retry_after = Some(seconds.max(1));
+        self
+    }
+
+    pub(crate) fn code(&self) -> &'static str {
+        self.code
+    }
+
+    pub(crate) fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Attaches additional structured information about the error.
+    pub(crate) fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status);
+        if self.status == StatusCode::UNAUTHORIZED {
+            response.insert_header(("WWW-Authenticate", "Bearer"));
+        }
+        if let Some(seconds) = self.retry_after {
+            response.insert_header(("Retry-After", seconds.to_string()));
+        }
+        response.json(ErrorResponse {
+            code: self.code.to_string(),
+            message: self.message.clone(),
+            job_id: self.job_id.clone(),
+            details: self.details.clone(),
+        })
+    }
+}
+
+impl ApiError {
+    /// Reports an engine error about the files of a job, naming them
+    /// relative to `job_dir` rather than by their server path.
+    pub(crate) fn job_error(err: Error, job_dir: &Path) -> Self {
+        let message = err.message_relative_to(job_dir);
+        ApiError::new(status_code(&err), err.code(), message)
+    }
+}
+
+// Names the file of the error by its file name alone, so that clients never
+// see server paths. Use `ApiError::job_error` for the files of a job.
+impl From<Error> for ApiError {
+    fn from(err: Error) -> Self {
+        let dir = err.path().and_then(Path::parent).unwrap_or(Path::new(""));
+        let message = err.message_relative_to(dir);
+        ApiError::new(status_code(&err), err.code(), message)
+    }
+}
+
+/// Maps an engine error to the HTTP status code that best describes it.
+pub(crate) fn status_code(err: &Error) -> StatusCode {
+    match err {
+        Error::UnreadableWorkbook { .. }
+        | Error::MissingSheet { .. }
+        | Error::InvalidDate { .. }
+        | Error::ZipRead { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        Error::NoExcelFiles(_) => StatusCode::NOT_FOUND,
+        Error::InvalidFilePath(_) | Error::InvalidPattern { .. } => {
+            StatusCode::BAD_REQUEST
+        }
+        Error::LimitExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        Error::DuplicateOutput { .. } | Error::Cancelled => {
+            StatusCode::CONFLICT
+        }
+        Error::Io { source, .. } if source.kind() == ErrorKind::NotFound => {
+            StatusCode::NOT_FOUND
+        }
+        Error::WorkbookWrite { .. }
+        | Error::ZipWrite { .. }
+        | Error::Io { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
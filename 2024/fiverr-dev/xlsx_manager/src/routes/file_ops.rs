@@ -1,42 +1,204 @@
+use crate::jobs::{INPUTS_DIR, UPLOAD_DIR};
 use std::fs;
 use std::path::{Path, PathBuf};
 
+/// Magic bytes of OLE2 compound documents (.xls)
+const OLE2_MAGIC: [u8; 8] = [0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+/// Magic bytes of a ZIP local file header (.xlsx, .zip)
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+/// Magic bytes of an empty ZIP archive
+const EMPTY_ZIP_MAGIC: [u8; 4] = [0x50, 0x4B, 0x05, 0x06];
+/// Number of leading bytes needed to recognize an uploaded file
+pub(crate) const MAGIC_LEN: usize = OLE2_MAGIC.len();
+/// Longest file name kept by `sanitize_filename`, in characters
+const MAX_FILENAME_LEN: usize = 200;
+
+/// Container format an accepted upload must be stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileKind {
+    Ole2,
+    Zip,
+}
+
+impl FileKind {
+    /// Returns the expected format for a file name, or `None` if the
+    /// extension is not one of .xls, .xlsx or .zip.
+    pub(crate) fn from_filename(filename: &str) -> Option<Self> {
+        match Path::new(filename).extension().and_then(|ext| ext.to_str()) {
+            Some("xls") => Some(FileKind::Ole2),
+            Some("xlsx") | Some("zip") => Some(FileKind::Zip),
+            _ => None,
+        }
+    }
+
+    /// Checks the first bytes of a file against the format's magic bytes.
+    pub(crate) fn matches(self, header: &[u8]) -> bool {
+        match self {
+            FileKind::Ole2 => header.starts_with(&OLE2_MAGIC),
+            FileKind::Zip => {
+                header.starts_with(&ZIP_MAGIC)
+                    || header.starts_with(&EMPTY_ZIP_MAGIC)
+            }
+        }
+    }
+}
+
 pub(crate) fn create_guid_directory(
     guid: &str,
 ) -> Result<PathBuf, std::io::Error> {
-    let directory_path = PathBuf::from(format!("upload/{}", guid));
-    // Create the directory, propagating any potential errors
-    fs::create_dir_all(&directory_path)?;
+    let directory_path = Path::new(UPLOAD_DIR).join(guid);
+    // Create the directory and its inputs folder, propagating any potential
+    // errors
+    fs::create_dir_all(directory_path.join(INPUTS_DIR))?;
     Ok(directory_path)
 }
 
-pub(crate) fn list_excel_files_in_directory(
-    directory_path: &Path,
-) -> Result<Vec<String>, String> {
-    let mut excel_file_names = Vec::new();
-
-    // Read the directory and collect all .xlsx and .xls file names
-    let entries = fs::read_dir(directory_path).map_err(|e| e.to_string())?;
-    for entry in entries {
-        let path = entry.map_err(|e| e.to_string())?.path();
-        // Check if the file extension is either .xlsx or .xls
-        if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
-            if extension == "xlsx" || extension == "xls" {
-                if let Some(file_name) =
-                    path.file_name().and_then(|name| name.to_str())
-                {
-                    excel_file_names.push(file_name.to_string());
+/// Reduces a client-supplied file name to a safe base name. Directory
+/// components are dropped, characters other than letters, digits and
+/// ` ._()-` are replaced with `_`, the extension is lowercased and names
+/// longer than `MAX_FILENAME_LEN` lose the end of their stem. Returns `None`
+/// if no usable name remains.
+pub(crate) fn sanitize_filename(filename: &str) -> Option<String> {
+    // Keep only the last path component, whichever separator was used
+    let base_name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+
+    let cleaned: String =
+        base_name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || " ._()-".contains(c) {
+                    c
+                } else {
+                    '_'
                 }
-            }
+            })
+            .collect();
+
+    // Leading dots would make hidden files (or `..`), trailing dots and
+    // spaces are stripped by some file systems
+    let cleaned = cleaned.trim_matches(|c| c == '.' || c == ' ');
+    if cleaned.is_empty() {
+        return None;
+    }
+
+    match cleaned.rsplit_once('.') {
+        Some((stem, extension))
+            if !stem.is_empty()
+                && extension.chars().count() + 1 < MAX_FILENAME_LEN =>
+        {
+            let stem_len = MAX_FILENAME_LEN - extension.chars().count() - 1;
+            let stem: String = stem.chars().take(stem_len).collect();
+            Some(format!("{}.{}", stem, extension.to_lowercase()))
+        }
+        _ => Some(cleaned.chars().take(MAX_FILENAME_LEN).collect()),
+    }
+}
+
+/// Returns a path for `filename` inside `directory` that does not exist yet,
+/// appending ` (1)`, ` (2)`, ... to the file stem on collisions.
+pub(crate) fn unique_file_path(directory: &Path, filename: &str) -> PathBuf {
+    let candidate = directory.join(filename);
+    if !candidate.exists() {
+        return candidate;
+    }
+
+    let (stem, extension) = match filename.rsplit_once('.') {
+        Some((stem, extension)) => (stem, format!(".{}", extension)),
+        None => (filename, String::new()),
+    };
+    (1..)
+        .map(|n| directory.join(format!("{} ({}){}", stem, n, extension)))
+        .find(|path| !path.exists())
+        .expect("an unused file name always exists")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn sanitize_filename_drops_directories() {
+        let sanitized = |name| sanitize_filename(name).unwrap();
+        assert_eq!(sanitized("../../etc/passwd"), "passwd");
+        assert_eq!(sanitized("..\\..\\windows\\report.XLSX"), "report.xlsx");
+        assert_eq!(sanitized("/abs/path/data.xls"), "data.xls");
+    }
+
+    #[test]
+    fn sanitize_filename_rejects_empty_names() {
+        for name in ["", ".", "..", "../", "dir/", " . ", "..\\.."] {
+            assert_eq!(sanitize_filename(name), None, "{:?}", name);
         }
     }
 
-    if excel_file_names.is_empty() {
-        return Err(
-            "No Excel files (.xlsx or .xls) found in the specified directory."
-                .to_string(),
-        );
+    #[test]
+    fn sanitize_filename_replaces_unsafe_characters() {
+        let sanitized = |name| sanitize_filename(name).unwrap();
+        assert_eq!(sanitized("sales;rm -rf*.xlsx"), "sales_rm -rf_.xlsx");
+        assert_eq!(sanitized(".hidden.xlsx"), "hidden.xlsx");
+        assert_eq!(sanitized("report (1).Xls. "), "report (1).xls");
+        assert_eq!(sanitized("no_extension"), "no_extension");
+    }
+
+    #[test]
+    fn sanitize_filename_truncates_the_stem_only() {
+        let sanitized = |name: &str| sanitize_filename(name).unwrap();
+        let long = sanitized(&format!("{}.xlsx", "a".repeat(250)));
+        assert_eq!(long.len(), MAX_FILENAME_LEN);
+        assert!(long.ends_with("a.xlsx"));
+        assert_eq!(FileKind::from_filename(&long), Some(FileKind::Zip));
+
+        assert_eq!(sanitized(&"a".repeat(500)).len(), MAX_FILENAME_LEN);
+        // An extension too long to keep is cut like the rest of the name
+        let long_extension = sanitized(&format!("a.{}", "b".repeat(500)));
+        assert_eq!(long_extension.len(), MAX_FILENAME_LEN);
+    }
+
+    #[test]
+    fn unique_file_path_appends_a_counter_on_collisions() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).unwrap();
+
+        assert_eq!(unique_file_path(&dir, "a.xlsx"), dir.join("a.xlsx"));
+        fs::write(dir.join("a.xlsx"), b"").unwrap();
+        assert_eq!(unique_file_path(&dir, "a.xlsx"), dir.join("a (1).xlsx"));
+        fs::write(dir.join("a (1).xlsx"), b"").unwrap();
+        assert_eq!(unique_file_path(&dir, "a.xlsx"), dir.join("a (2).xlsx"));
+
+        fs::write(dir.join("notes"), b"").unwrap();
+        assert_eq!(unique_file_path(&dir, "notes"), dir.join("notes (1)"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_kind_follows_the_extension() {
+        assert_eq!(FileKind::from_filename("a.xls"), Some(FileKind::Ole2));
+        assert_eq!(FileKind::from_filename("a.xlsx"), Some(FileKind::Zip));
+        assert_eq!(FileKind::from_filename("a.zip"), Some(FileKind::Zip));
+        assert_eq!(FileKind::from_filename("a.csv"), None);
+        assert_eq!(FileKind::from_filename("xlsx"), None);
     }
 
-    Ok(excel_file_names)
+    #[test]
+    fn file_kind_matches_magic_bytes() {
+        let ole2 = OLE2_MAGIC;
+        let zip = [0x50, 0x4B, 0x03, 0x04, 0x14, 0x00, 0x06, 0x00];
+        let empty_zip = [0x50, 0x4B, 0x05, 0x06, 0x00, 0x00, 0x00, 0x00];
+
+        assert!(FileKind::Ole2.matches(&ole2));
+        assert!(FileKind::Zip.matches(&zip));
+        assert!(FileKind::Zip.matches(&empty_zip));
+
+        // Content that does not match the extension
+        assert!(!FileKind::Ole2.matches(&zip));
+        assert!(!FileKind::Zip.matches(&ole2));
+        assert!(!FileKind::Zip.matches(b"col1,col2\n1,2"));
+        assert!(!FileKind::Ole2.matches(b"%PDF-1.7"));
+        // Files shorter than the magic bytes
+        assert!(!FileKind::Ole2.matches(&ole2[..4]));
+        assert!(!FileKind::Zip.matches(&[0x50, 0x4B]));
+        assert!(!FileKind::Zip.matches(&[]));
+    }
 }
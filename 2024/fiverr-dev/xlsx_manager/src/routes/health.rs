@@ -0,0 +1,86 @@
+use crate::config::AppConfig;
+use crate::jobs::UPLOAD_DIR;
+use crate::limits::JobSlots;
+use crate::routes::response::{Check, ReadinessChecks, ReadinessResponse};
+use crate::shutdown::Shutdown;
+use actix_web::{web, HttpResponse};
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use uuid::Uuid;
+
+pub(crate) fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/healthz", web::get().to(healthz))
+        .route("/readyz", web::get().to(readyz));
+}
+
+// Liveness: answering at all means the server is alive.
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+// Readiness: the server can take new jobs. Answers 503 when a check fails.
+async fn readyz(
+    config: web::Data<AppConfig>,
+    job_slots: web::Data<JobSlots>,
+    shutdown: web::Data<Shutdown>,
+) -> HttpResponse {
+    let min_free = config.health.min_free_disk_space;
+    let (upload_dir, disk_space) = web::block(move || {
+        let upload_dir = Path::new(UPLOAD_DIR);
+        (check_writable(upload_dir), check_free_space(upload_dir, min_free))
+    })
+    .await
+    .unwrap_or_else(|_| {
+        let error = || Err("Check did not complete".to_string());
+        (error(), error())
+    });
+    let job_queue = if shutdown.is_draining() {
+        Err("The server is shutting down".to_string())
+    } else if job_slots.is_saturated() {
+        Err("Every processing slot is taken and the queue is full".to_string())
+    } else {
+        Ok(())
+    };
+
+    let checks = ReadinessChecks {
+        upload_dir: Check::from_result(upload_dir),
+        disk_space: Check::from_result(disk_space),
+        job_queue: Check::from_result(job_queue),
+    };
+    let ready =
+        checks.upload_dir.ok && checks.disk_space.ok && checks.job_queue.ok;
+    let mut response = if ready {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    response.json(ReadinessResponse {
+        status: if ready { "ready" } else { "not_ready" },
+        checks,
+    })
+}
+
+// Writes and removes a probe file in the upload folder.
+fn check_writable(upload_dir: &Path) -> Result<(), String> {
+    let probe = upload_dir.join(format!(".readyz-{}", Uuid::new_v4()));
+    fs::create_dir_all(upload_dir)
+        .and_then(|()| fs::write(&probe, b"ok"))
+        .and_then(|()| fs::remove_file(&probe))
+        .map_err(|err| {
+            format!("Upload folder {} is not writable: {}", UPLOAD_DIR, err)
+        })
+}
+
+fn check_free_space(upload_dir: &Path, min_free: u64) -> Result<(), String> {
+    let free = fs4::available_space(upload_dir).map_err(|err| {
+        format!("Failed to read the free space of {}: {}", UPLOAD_DIR, err)
+    })?;
+    if free < min_free {
+        return Err(format!(
+            "{} bytes free, {} bytes required",
+            free, min_free
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use crate::janitor::disk_usage;
+use crate::jobs::{JobStore, UPLOAD_DIR};
+use crate::metrics::Metrics;
+use crate::routes::error::ApiError;
+use actix_web::{web, HttpResponse};
+use log::error;
+use std::path::Path;
+
+pub(crate) fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(metrics));
+}
+
+async fn metrics(
+    metrics: web::Data<Metrics>,
+    store: web::Data<JobStore>,
+) -> Result<HttpResponse, ApiError> {
+    // Reading every job and walking the upload folder touch the disk
+    let (jobs, upload_dir_bytes) = web::block(move || {
+        let jobs = store.list().unwrap_or_else(|err| {
+            error!("Failed to list jobs: {}", err);
+            Vec::new()
+        });
+        (jobs, disk_usage(Path::new(UPLOAD_DIR)))
+    })
+    .await
+    .map_err(|_| ApiError::internal("io_error", "Failed to collect metrics"))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render(&jobs, upload_dir_bytes)))
+}
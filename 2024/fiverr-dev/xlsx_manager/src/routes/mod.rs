@@ -1,6 +1,10 @@
 pub mod api;
+mod auth;
+mod error;
 mod file_ops;
+pub mod health;
 pub mod index;
+pub mod metrics;
+pub mod profiles;
 mod request;
 mod response;
-mod zip_ops;
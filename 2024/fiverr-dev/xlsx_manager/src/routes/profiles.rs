@@ -0,0 +1,151 @@
+use crate::profiles::{Profile, ProfileStore};
+use crate::routes::auth::Principal;
+use crate::routes::error::ApiError;
+use crate::routes::request::{ProfileExportQuery, SaveProfileRequest};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use log::{error, info};
+use serde_json::json;
+
+pub(crate) fn configure_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/profiles")
+            .route(web::get().to(list_profiles))
+            .route(web::post().to(save_profile)),
+    )
+    .service(
+        web::resource("/profiles/{name}").route(web::get().to(get_profile)),
+    )
+    .service(
+        web::resource("/profiles/{name}/export")
+            .route(web::get().to(export_profile)),
+    );
+}
+
+async fn save_profile(
+    request: web::Json<SaveProfileRequest>,
+    profiles: web::Data<ProfileStore>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
+    let request = request.into_inner();
+    if !ProfileStore::is_valid_name(&request.name) {
+        return Err(ApiError::bad_request(
+            "invalid_profile_name",
+            "Profile names are 1 to 64 letters, digits, '-' or '_'",
+        )
+        .with_details(json!({ "name": request.name })));
+    }
+
+    // Reject options that could not be used by `/process`
+    request.options.process_options()?;
+
+    // Only the owner of a profile may add versions to it
+    let profile = profiles
+        .save(
+            &request.name,
+            principal.name(),
+            request.description,
+            request.options,
+            |profile| principal.can_access(profile.owner.as_deref()),
+        )
+        .map_err(profile_store_error)?
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::CONFLICT,
+                "profile_name_taken",
+                format!(
+                    "Profile {} belongs to another principal",
+                    request.name
+                ),
+            )
+            .with_details(json!({ "profile": request.name }))
+        })?;
+    info!(
+        "Saved profile {} as version {}",
+        profile.name,
+        profile.versions.len()
+    );
+    Ok(HttpResponse::Created().json(profile))
+}
+
+async fn list_profiles(
+    profiles: web::Data<ProfileStore>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
+    let mut profiles = profiles.list().map_err(profile_store_error)?;
+    profiles.retain(|profile| principal.can_access(profile.owner.as_deref()));
+    Ok(HttpResponse::Ok().json(profiles))
+}
+
+async fn get_profile(
+    name: web::Path<String>,
+    profiles: web::Data<ProfileStore>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
+    let profile = find_profile(&profiles, &principal, &name)?;
+    Ok(HttpResponse::Ok().json(profile))
+}
+
+async fn export_profile(
+    name: web::Path<String>,
+    query: web::Query<ProfileExportQuery>,
+    profiles: web::Data<ProfileStore>,
+    principal: Principal,
+) -> Result<HttpResponse, ApiError> {
+    let mut profile = find_profile(&profiles, &principal, &name)?;
+
+    // Export a single version when one is asked for
+    if let Some(version) = query.version {
+        let selected = profile
+            .version(Some(version))
+            .cloned()
+            .ok_or_else(|| profile_version_not_found(&profile.name, version))?;
+        profile.versions = vec![selected];
+    }
+
+    let content = toml::to_string_pretty(&profile).map_err(|err| {
+        error!("Failed to export profile {}: {}", profile.name, err);
+        ApiError::internal("export_failed", err.to_string())
+    })?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/toml")
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.toml\"", profile.name),
+        ))
+        .body(content))
+}
+
+/// Loads a profile, reporting a missing one, or one the caller may not
+/// access, as not found.
+pub(crate) fn find_profile(
+    profiles: &ProfileStore,
+    principal: &Principal,
+    name: &str,
+) -> Result<Profile, ApiError> {
+    profiles
+        .get(name)
+        .map_err(profile_store_error)?
+        .filter(|profile| principal.can_access(profile.owner.as_deref()))
+        .ok_or_else(|| {
+            ApiError::not_found(
+                "profile_not_found",
+                format!("Profile {} not found", name),
+            )
+            .with_details(json!({ "profile": name }))
+        })
+}
+
+pub(crate) fn profile_version_not_found(name: &str, version: u32) -> ApiError {
+    ApiError::not_found(
+        "profile_not_found",
+        format!("Profile {} has no version {}", name, version),
+    )
+    .with_details(json!({ "profile": name, "version": version }))
+}
+
+// Reports a failure to read or write a profile file.
+fn profile_store_error(err: std::io::Error) -> ApiError {
+    error!("Profile store error: {}", err);
+    ApiError::internal("io_error", format!("Profile store error: {}", err))
+}
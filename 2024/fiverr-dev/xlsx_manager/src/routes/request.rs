@@ -1,12 +1,175 @@
+use crate::jobs::{JobOptions, JobOverride, JobRecord, JobStatus};
+use crate::routes::response::JobListResponse;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct NumberOfRowsToDeleteRequest {
     pub job_id: String,
+    #[serde(default)]
     pub num_rows_to_delete: u32,
+    /// Detect the banner rows of each file instead of deleting
+    /// `num_rows_to_delete` rows
+    #[serde(default)]
+    pub auto_detect_rows: bool,
+    /// Options for specific files, keyed by file name or glob pattern. The
+    /// first matching override applies; other files use the defaults above.
+    #[serde(default)]
+    pub overrides: Vec<JobOverride>,
+    /// Saved profile to take all options from instead of the fields above
+    pub profile: Option<String>,
+    /// Version of `profile`, the latest when omitted
+    pub profile_version: Option<u32>,
 }
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct ZipFileDownloadRequest {
     pub file_url: String,
 }
+
+/// Query string of `GET /jobs`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct JobListQuery {
+    /// 1-based page number
+    #[serde(default = "default_page")]
+    pub page: usize,
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+    pub status: Option<JobStatus>,
+    /// Only jobs created at or after this time (RFC 3339)
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only jobs created before this time (RFC 3339)
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl JobListQuery {
+    /// Tells whether a job passes the status and creation time filters.
+    pub(crate) fn matches(&self, job: &JobRecord) -> bool {
+        self.status.is_none_or(|status| job.status == status)
+            && self.created_after.is_none_or(|after| job.created_at >= after)
+            && self.created_before.is_none_or(|before| job.created_at < before)
+    }
+
+    /// Returns the requested page of the jobs passing the filters, newest
+    /// first.
+    pub(crate) fn page_of(&self, jobs: Vec<JobRecord>) -> JobListResponse {
+        let mut jobs: Vec<_> =
+            jobs.into_iter().filter(|job| self.matches(job)).collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.created_at));
+
+        let total = jobs.len();
+        let jobs = jobs
+            .into_iter()
+            .skip(self.page.saturating_sub(1).saturating_mul(self.per_page))
+            .take(self.per_page)
+            .collect();
+        JobListResponse {
+            jobs,
+            page: self.page,
+            per_page: self.per_page,
+            total,
+        }
+    }
+}
+
+fn default_page() -> usize {
+    1
+}
+
+fn default_per_page() -> usize {
+    20
+}
+
+/// Query string of `GET /jobs/{job_id}/preview`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct PreviewQuery {
+    /// Options the result preview is computed with
+    #[serde(default)]
+    pub num_rows_to_delete: usize,
+    #[serde(default)]
+    pub auto_detect_rows: bool,
+    /// Number of rows shown per sheet
+    #[serde(default = "default_preview_rows")]
+    pub rows: usize,
+}
+
+fn default_preview_rows() -> usize {
+    50
+}
+
+/// Body of `POST /profiles`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct SaveProfileRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub options: JobOptions,
+}
+
+/// Query string of `GET /profiles/{name}/export`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ProfileExportQuery {
+    /// Export only this version instead of the whole history
+    pub version: Option<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web;
+    use chrono::TimeZone;
+
+    // Jobs created on January 1st to 5th, the even days completed.
+    fn jobs() -> Vec<JobRecord> {
+        (1..=5)
+            .map(|day| {
+                let mut job = JobRecord::new(day.to_string(), Vec::new());
+                job.created_at =
+                    Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap();
+                if day % 2 == 0 {
+                    job.status = JobStatus::Completed;
+                }
+                job
+            })
+            .collect()
+    }
+
+    fn query(params: &str) -> JobListQuery {
+        web::Query::<JobListQuery>::from_query(params).unwrap().into_inner()
+    }
+
+    fn ids(response: &JobListResponse) -> Vec<&str> {
+        response.jobs.iter().map(|job| job.job_id.as_str()).collect()
+    }
+
+    #[test]
+    fn job_pages_are_newest_first() {
+        let first = query("per_page=2").page_of(jobs());
+        assert_eq!(ids(&first), ["5", "4"]);
+        assert_eq!((first.page, first.per_page, first.total), (1, 2, 5));
+
+        let last = query("page=3&per_page=2").page_of(jobs());
+        assert_eq!(ids(&last), ["1"]);
+        let past_the_end = query("page=4&per_page=2").page_of(jobs());
+        assert!(past_the_end.jobs.is_empty());
+        assert_eq!(past_the_end.total, 5);
+    }
+
+    #[test]
+    fn job_filters_apply_before_paging() {
+        let completed = query("status=completed&per_page=1").page_of(jobs());
+        assert_eq!(ids(&completed), ["4"]);
+        assert_eq!(completed.total, 2);
+
+        // `created_after` is inclusive, `created_before` exclusive
+        let range = query(
+            "created_after=2024-01-02T00:00:00Z\
+             &created_before=2024-01-04T00:00:00Z",
+        )
+        .page_of(jobs());
+        assert_eq!(ids(&range), ["3", "2"]);
+
+        let none = query("status=failed").page_of(jobs());
+        assert!(none.jobs.is_empty());
+        assert_eq!(none.total, 0);
+    }
+}
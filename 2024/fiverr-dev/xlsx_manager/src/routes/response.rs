@@ -1,9 +1,95 @@
+use crate::jobs::{FailedFile, JobRecord};
 use serde::Serialize;
+use std::collections::BTreeMap;
+use xlsx_manager::WorkbookPreview;
 
 #[derive(Serialize)]
 pub(crate) struct ProcessResponse {
     pub job_id: String,
+    /// Version of the run that produced the output
+    pub version: u32,
     pub time_taken: String,
     pub num_rows_deleted: usize,
+    /// Number of rows deleted from each processed file, which differs from
+    /// `num_rows_deleted` when banner rows are detected
+    pub rows_deleted: BTreeMap<String, usize>,
     pub zip_file_name: String,
+    pub failed_files: Vec<FailedFile>,
+}
+
+/// One page of `GET /jobs`, newest jobs first.
+#[derive(Serialize)]
+pub(crate) struct JobListResponse {
+    pub jobs: Vec<JobRecord>,
+    pub page: usize,
+    pub per_page: usize,
+    /// Number of jobs matching the filters, across all pages
+    pub total: usize,
+}
+
+/// Response of `GET /jobs/{job_id}/preview`.
+#[derive(Serialize)]
+pub(crate) struct PreviewResponse {
+    pub job_id: String,
+    pub num_rows_to_delete: usize,
+    pub auto_detect_rows: bool,
+    pub rows: usize,
+    pub files: Vec<FilePreview>,
+}
+
+/// Preview of one uploaded workbook, or the reason it cannot be read. Files
+/// inside a Zip file are named `archive.zip/file.xlsx`.
+#[derive(Serialize)]
+pub(crate) struct FilePreview {
+    pub file: String,
+    #[serde(flatten)]
+    pub preview: Option<WorkbookPreview>,
+    pub error: Option<FileError>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct FileError {
+    pub code: String,
+    pub message: String,
+}
+
+/// Response of `GET /readyz`.
+#[derive(Serialize)]
+pub(crate) struct ReadinessResponse {
+    /// `ready`, or `not_ready` if any check failed
+    pub status: &'static str,
+    pub checks: ReadinessChecks,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ReadinessChecks {
+    pub upload_dir: Check,
+    pub disk_space: Check,
+    pub job_queue: Check,
+}
+
+/// Outcome of one readiness check, with the reason it failed.
+#[derive(Serialize)]
+pub(crate) struct Check {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    pub(crate) fn from_result(result: Result<(), String>) -> Self {
+        match result {
+            Ok(()) => Check { ok: true, error: None },
+            Err(error) => Check { ok: false, error: Some(error) },
+        }
+    }
+}
+
+/// JSON body returned when a request fails.
+#[derive(Serialize)]
+pub(crate) struct ErrorResponse {
+    pub code: String,
+    pub message: String,
+    pub job_id: Option<String>,
+    pub details: Option<serde_json::Value>,
 }
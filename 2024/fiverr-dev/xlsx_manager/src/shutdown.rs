@@ -0,0 +1,99 @@
+use crate::progress::ProgressHub;
+use actix_web::dev::ServerHandle;
+use actix_web::rt::signal;
+use actix_web::rt::time::{sleep, timeout as with_timeout};
+use actix_web::web;
+use futures::future::join_all;
+use log::{info, warn};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// How often the running jobs are counted while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Time the calls of interrupted jobs get to record the interruption and
+/// answer after the shutdown timeout
+const INTERRUPT_GRACE: Duration = Duration::from_secs(5);
+
+/// Progress of the shutdown of the server, checked by the handlers.
+#[derive(Default)]
+pub(crate) struct Shutdown {
+    // New jobs are turned away
+    draining: AtomicBool,
+    // Jobs still running after the timeout are being cancelled
+    interrupting: AtomicBool,
+}
+
+impl Shutdown {
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Whether running jobs are cancelled because the server is stopping.
+    pub(crate) fn is_interrupting(&self) -> bool {
+        self.interrupting.load(Ordering::SeqCst)
+    }
+}
+
+/// Waits for SIGINT or SIGTERM, then stops taking new jobs and gives the
+/// running ones `timeout` to finish. Jobs still running after that are
+/// cancelled. The servers stop once their requests are done, within what is
+/// left of `timeout`, or within `INTERRUPT_GRACE` if jobs were interrupted.
+/// Returns when the servers stopped or that time is up; the caller then
+/// exits, dropping the requests still open.
+pub(crate) async fn watch(
+    shutdown: web::Data<Shutdown>,
+    progress: web::Data<ProgressHub>,
+    servers: Vec<ServerHandle>,
+    timeout: Duration,
+) {
+    wait_for_signal().await;
+    shutdown.draining.store(true, Ordering::SeqCst);
+    info!(
+        "Shutting down, waiting up to {}s for {} running jobs",
+        timeout.as_secs(),
+        progress.running_jobs()
+    );
+
+    let deadline = Instant::now() + timeout;
+    while progress.running_jobs() > 0 && Instant::now() < deadline {
+        sleep(DRAIN_POLL_INTERVAL).await;
+    }
+    let running = progress.running_jobs();
+    let stop_deadline = if running > 0 {
+        warn!("Interrupting {} jobs still running", running);
+        shutdown.interrupting.store(true, Ordering::SeqCst);
+        progress.cancel_all();
+        Instant::now() + INTERRUPT_GRACE
+    } else {
+        deadline
+    };
+
+    // Interrupted `/process` calls record their outcome before the workers
+    // stop
+    let stop = join_all(servers.iter().map(|server| server.stop(true)));
+    let remaining = stop_deadline.saturating_duration_since(Instant::now());
+    if with_timeout(remaining, stop).await.is_err() {
+        warn!("Dropping the requests still open after the shutdown timeout");
+    }
+}
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                let ctrl_c = pin!(signal::ctrl_c());
+                let terminate = pin!(terminate.recv());
+                futures::future::select(ctrl_c, terminate).await;
+                return;
+            }
+            Err(err) => warn!("Failed to listen for SIGTERM: {}", err),
+        }
+    }
+    if let Err(err) = signal::ctrl_c().await {
+        warn!("Failed to listen for Ctrl-C: {}", err);
+        std::future::pending::<()>().await;
+    }
+}
@@ -0,0 +1,146 @@
+use crate::config::TlsConfig;
+use actix_web::http::header::LOCATION;
+use actix_web::http::uri::Authority;
+use actix_web::rt::time;
+use actix_web::{web, HttpRequest, HttpResponse};
+use log::{error, info};
+use rustls::crypto::ring::{default_provider, sign::any_supported_type};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{InconsistentKeys, ServerConfig};
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// Serves the certificate currently loaded from the `[tls]` files, which
+/// [`watch`] replaces when they change.
+#[derive(Debug)]
+pub(crate) struct CertResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    fn set(&self, key: CertifiedKey) {
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) =
+            Arc::new(key);
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+}
+
+/// Loads the certificate and key, returning the rustls configuration of the
+/// HTTPS listener and the resolver to reload them through.
+pub(crate) fn server_config(
+    config: &TlsConfig,
+) -> io::Result<(ServerConfig, Arc<CertResolver>)> {
+    let key = load_certified_key(&config.cert, &config.key)?;
+    let resolver =
+        Arc::new(CertResolver { current: RwLock::new(Arc::new(key)) });
+    let server_config =
+        ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(invalid_data)?
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+    Ok((server_config, resolver))
+}
+
+/// Reloads the certificate whenever its files change. Runs until the server
+/// stops; a change that cannot be loaded keeps the previous certificate.
+pub(crate) async fn watch(config: TlsConfig, resolver: Arc<CertResolver>) {
+    let mut last_modified = modified(&config);
+    let mut interval =
+        time::interval(Duration::from_secs(config.reload_interval.max(1)));
+    loop {
+        interval.tick().await;
+
+        let current = modified(&config);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+        match load_certified_key(&config.cert, &config.key) {
+            Ok(key) => {
+                resolver.set(key);
+                info!("Reloaded TLS certificate {}", config.cert.display());
+            }
+            Err(err) => error!(
+                "Failed to reload TLS certificate {}, keeping the previous \
+                 one: {}",
+                config.cert.display(),
+                err
+            ),
+        }
+    }
+}
+
+/// Redirects a plain-HTTP request to the same URL over HTTPS on
+/// `https_port`.
+pub(crate) async fn redirect(
+    req: HttpRequest,
+    https_port: web::Data<u16>,
+) -> HttpResponse {
+    let connection = req.connection_info();
+    let host = connection
+        .host()
+        .parse::<Authority>()
+        .map(|authority| authority.host().to_string())
+        .unwrap_or_default();
+    let port = match **https_port {
+        443 => String::new(),
+        port => format!(":{}", port),
+    };
+    let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    HttpResponse::PermanentRedirect()
+        .insert_header((LOCATION, format!("https://{}{}{}", host, port, path)))
+        .finish()
+}
+
+fn load_certified_key(cert: &Path, key: &Path) -> io::Result<CertifiedKey> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))
+        .collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No certificate found in {}", cert.display()),
+        ));
+    }
+    let private_key =
+        rustls_pemfile::private_key(&mut BufReader::new(File::open(key)?))?
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("No private key found in {}", key.display()),
+                )
+            })?;
+
+    let certified_key = CertifiedKey::new(
+        certs,
+        any_supported_type(&private_key).map_err(invalid_data)?,
+    );
+    // Refuse a key that does not belong to the certificate, which would
+    // fail every handshake
+    match certified_key.keys_match() {
+        Ok(())
+        | Err(rustls::Error::InconsistentKeys(InconsistentKeys::Unknown)) => {
+            Ok(certified_key)
+        }
+        Err(err) => Err(invalid_data(err)),
+    }
+}
+
+// Modification times of the certificate and key files.
+fn modified(config: &TlsConfig) -> Option<(SystemTime, SystemTime)> {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified());
+    Some((modified(&config.cert).ok()?, modified(&config.key).ok()?))
+}
+
+fn invalid_data(err: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
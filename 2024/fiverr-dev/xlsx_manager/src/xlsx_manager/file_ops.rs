@@ -1,35 +1,23 @@
+use crate::error::{Error, Result};
 use log::info;
 use std::{
-    fs, io,
+    fs,
     path::{Path, PathBuf},
 };
-use thiserror::Error;
-
-/// Custom error type for file operations
-#[derive(Debug, Error)]
-pub enum FileOpsError {
-    #[error("Invalid source file path: {0}")]
-    InvalidFilePath(String),
-    #[error("IO error")]
-    IoError(#[from] io::Error),
-}
 
 /// Ensures that the specified directory exists. If it doesn't exist, create it.
-pub fn create_directory_if_missing(
-    directory_path: &str,
-) -> Result<(), FileOpsError> {
-    let directory = Path::new(directory_path);
+pub(crate) fn create_directory_if_missing(directory: &Path) -> Result<()> {
     if !directory.exists() {
-        fs::create_dir_all(directory).map_err(FileOpsError::IoError)?;
-        info!("Created directory: {}", directory_path);
+        fs::create_dir_all(directory).map_err(Error::io(directory))?;
+        info!("Created directory: {}", directory.display());
     } else {
-        info!("Directory already exists: {}", directory_path);
+        info!("Directory already exists: {}", directory.display());
     }
     Ok(())
 }
 
 /// Determines whether the provided file path has an Excel-compatible extension (.xls or .xlsx).
-pub fn has_excel_extension(file_path: &Path) -> bool {
+pub(crate) fn has_excel_extension(file_path: &Path) -> bool {
     match file_path.extension().and_then(|ext| ext.to_str()) {
         Some("xls") | Some("xlsx") => true,
         _ => {
@@ -41,25 +29,36 @@ pub fn has_excel_extension(file_path: &Path) -> bool {
 
 /// Generates the full output file path in the specified `output_directory` for the processed file.
 /// The output file will have the same extension (.xls or .xlsx) as the source file.
-pub fn generate_output_file_path(
+pub(crate) fn generate_output_file_path(
     source_file_path: &Path,
-    output_directory: &str,
-) -> Result<PathBuf, FileOpsError> {
+    output_directory: &Path,
+) -> Result<PathBuf> {
     // Ensure the output directory exists
     create_directory_if_missing(output_directory)?;
 
+    // Generate the full output path by combining the output directory and new file name
+    let output_file_path =
+        output_directory.join(output_file_name(source_file_path)?);
+
+    info!("Generated output file path: {:?}", output_file_path);
+    Ok(output_file_path)
+}
+
+/// Name of the processed file written for `source_file_path`. Sources with
+/// the same stem, such as `a.xls` and `a.xlsx`, get the same name.
+pub(crate) fn output_file_name(source_file_path: &Path) -> Result<String> {
     // Extract the base file name (without extension) from the source file path
     let file_name_without_extension = source_file_path
         .file_stem()
         .ok_or_else(|| {
-            FileOpsError::InvalidFilePath(format!(
+            Error::InvalidFilePath(format!(
                 "No file name found in path: {:?}",
                 source_file_path
             ))
         })?
         .to_str()
         .ok_or_else(|| {
-            FileOpsError::InvalidFilePath(format!(
+            Error::InvalidFilePath(format!(
                 "Invalid UTF-8 sequence in filename: {:?}",
                 source_file_path
             ))
@@ -70,7 +69,7 @@ pub fn generate_output_file_path(
     //     .extension()
     //     .and_then(|ext| ext.to_str())
     //     .ok_or_else(|| {
-    //         FileOpsError::InvalidFilePath(format!(
+    //         Error::InvalidFilePath(format!(
     //             "No file extension found in path: {:?}",
     //             source_file_path
     //         ))
@@ -79,11 +78,36 @@ pub fn generate_output_file_path(
     // Construct the new file name with the same extension
     // let new_file_name =
     //     format!("{}.{}", file_name_without_extension, file_extension);
-    let new_file_name = format!("{}.xlsx", file_name_without_extension);
+    Ok(format!("{}.xlsx", file_name_without_extension))
+}
 
-    // Generate the full output path by combining the output directory and new file name
-    let output_file_path = Path::new(output_directory).join(new_file_name);
+/// Lists the names of the Excel files (.xlsx or .xls) found directly inside
+/// `directory_path`. Returns an error if there are none.
+pub fn list_excel_files_in_directory(
+    directory_path: &Path,
+) -> Result<Vec<String>> {
+    let mut excel_file_names = Vec::new();
 
-    info!("Generated output file path: {:?}", output_file_path);
-    Ok(output_file_path)
+    // Read the directory and collect all .xlsx and .xls file names
+    for entry in
+        fs::read_dir(directory_path).map_err(Error::io(directory_path))?
+    {
+        let path = entry.map_err(Error::io(directory_path))?.path();
+        // Check if the file extension is either .xlsx or .xls
+        if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+            if extension == "xlsx" || extension == "xls" {
+                if let Some(file_name) =
+                    path.file_name().and_then(|name| name.to_str())
+                {
+                    excel_file_names.push(file_name.to_string());
+                }
+            }
+        }
+    }
+
+    if excel_file_names.is_empty() {
+        return Err(Error::NoExcelFiles(directory_path.to_path_buf()));
+    }
+
+    Ok(excel_file_names)
 }
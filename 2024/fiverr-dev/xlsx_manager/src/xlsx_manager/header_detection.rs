@@ -0,0 +1,168 @@
+use calamine::{Data, Range};
+
+/// Number of leading rows inspected when looking for the table header
+pub(crate) const SCAN_ROWS: usize = 100;
+/// Number of rows after a header candidate that must look like table rows
+const CONFIRM_ROWS: usize = 3;
+/// Number of leading rows [`detect_header_rows`] looks at
+pub(crate) const DETECTION_ROWS: usize = SCAN_ROWS + CONFIRM_ROWS;
+
+/// Infers how many banner rows (report title, run date, blank rows, ...)
+/// sit above the data table of a sheet, i.e. how many rows to delete so the
+/// sheet starts with the table's header row.
+///
+/// The header is the first row that has at least two cells, mostly text,
+/// and is followed by rows about as wide as itself. Returns `0` when no such
+/// row is found within the first rows of the sheet.
+pub fn detect_header_rows(range: &Range<Data>) -> usize {
+    let widths: Vec<(usize, usize)> = range
+        .rows()
+        .take(DETECTION_ROWS)
+        .map(|row| {
+            let filled = row.iter().filter(|cell| !is_blank(cell)).count();
+            let text = row.iter().filter(|cell| is_text(cell)).count();
+            (filled, text)
+        })
+        .collect();
+
+    (0..widths.len().min(SCAN_ROWS))
+        .find(|&index| is_header_row(&widths, index))
+        .unwrap_or(0)
+}
+
+// A header has at least two cells, at least half of them text, and the next
+// non-blank rows fill between half and all of its columns (plus one, for a
+// trailing total or note column).
+fn is_header_row(widths: &[(usize, usize)], index: usize) -> bool {
+    let (filled, text) = widths[index];
+    if filled < 2 || text * 2 < filled {
+        return false;
+    }
+
+    let following: Vec<usize> = widths[index + 1..]
+        .iter()
+        .map(|&(filled, _)| filled)
+        .filter(|&filled| filled > 0)
+        .take(CONFIRM_ROWS)
+        .collect();
+    following.iter().all(|&width| width * 2 >= filled && width <= filled + 1)
+}
+
+fn is_text(cell: &Data) -> bool {
+    matches!(cell, Data::String(s) if !s.trim().is_empty())
+}
+
+fn is_blank(cell: &Data) -> bool {
+    match cell {
+        Data::Empty => true,
+        Data::String(s) => s.trim().is_empty(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn banner() -> Vec<Data> {
+        vec![Data::String("Monthly report".to_string())]
+    }
+
+    fn header(width: usize) -> Vec<Data> {
+        (0..width).map(|i| Data::String(format!("Column {}", i))).collect()
+    }
+
+    fn data(width: usize) -> Vec<Data> {
+        (0..width).map(|i| Data::Float(i as f64)).collect()
+    }
+
+    fn sheet(rows: Vec<Vec<Data>>) -> Range<Data> {
+        let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+        if rows.is_empty() || width == 0 {
+            return Range::empty();
+        }
+        let mut range =
+            Range::new((0, 0), (rows.len() as u32 - 1, width as u32 - 1));
+        for (row_idx, row) in rows.into_iter().enumerate() {
+            for (col_idx, cell) in row.into_iter().enumerate() {
+                range.set_value((row_idx as u32, col_idx as u32), cell);
+            }
+        }
+        range
+    }
+
+    // `banners` banner rows, then a header and `data_rows` rows of its width
+    fn table(banners: usize, width: usize, data_rows: usize) -> Vec<Vec<Data>> {
+        let mut rows: Vec<_> = (0..banners).map(|_| banner()).collect();
+        rows.push(header(width));
+        rows.extend((0..data_rows).map(|_| data(width)));
+        rows
+    }
+
+    #[test]
+    fn counts_banner_rows_above_the_table() {
+        let mut rows = vec![banner(), vec![], banner()];
+        rows.extend(table(0, 3, 5));
+        assert_eq!(detect_header_rows(&sheet(rows)), 3);
+        assert_eq!(detect_header_rows(&sheet(table(0, 3, 5))), 0);
+    }
+
+    #[test]
+    fn empty_sheet_has_no_banner_rows() {
+        assert_eq!(detect_header_rows(&Range::empty()), 0);
+        assert_eq!(detect_header_rows(&sheet(vec![vec![], vec![]])), 0);
+    }
+
+    #[test]
+    fn header_without_data_rows_is_accepted() {
+        assert_eq!(detect_header_rows(&sheet(table(2, 4, 0))), 2);
+    }
+
+    #[test]
+    fn sheet_without_header_has_no_banner_rows() {
+        let numbers = (0..10).map(|_| data(4)).collect();
+        assert_eq!(detect_header_rows(&sheet(numbers)), 0);
+        let banners = (0..10).map(|_| banner()).collect();
+        assert_eq!(detect_header_rows(&sheet(banners)), 0);
+    }
+
+    #[test]
+    fn header_must_be_within_the_scanned_rows() {
+        let last_scanned = table(SCAN_ROWS - 1, 3, CONFIRM_ROWS);
+        assert_eq!(detect_header_rows(&sheet(last_scanned)), SCAN_ROWS - 1);
+        let past_scanned = table(SCAN_ROWS, 3, CONFIRM_ROWS);
+        assert_eq!(detect_header_rows(&sheet(past_scanned)), 0);
+    }
+
+    #[test]
+    fn only_the_confirm_rows_must_match_the_header() {
+        // A narrow row right after the confirm rows is ignored
+        let mut rows = table(1, 4, CONFIRM_ROWS);
+        rows.push(data(1));
+        assert_eq!(detect_header_rows(&sheet(rows)), 1);
+
+        // One within them rejects the header
+        let mut rows = table(1, 4, CONFIRM_ROWS - 1);
+        rows.push(data(1));
+        assert_eq!(detect_header_rows(&sheet(rows)), 0);
+    }
+
+    #[test]
+    fn blank_rows_after_the_header_are_skipped() {
+        let mut rows = vec![banner(), header(3), vec![], vec![]];
+        rows.extend((0..CONFIRM_ROWS).map(|_| data(3)));
+        assert_eq!(detect_header_rows(&sheet(rows)), 1);
+    }
+
+    #[test]
+    fn rows_wider_than_the_header_reject_it() {
+        let mut rows = vec![banner(), header(2)];
+        rows.extend((0..CONFIRM_ROWS).map(|_| data(4)));
+        assert_eq!(detect_header_rows(&sheet(rows)), 0);
+
+        // One extra column, e.g. a total, is allowed
+        let mut rows = vec![banner(), header(3)];
+        rows.extend((0..CONFIRM_ROWS).map(|_| data(4)));
+        assert_eq!(detect_header_rows(&sheet(rows)), 1);
+    }
+}
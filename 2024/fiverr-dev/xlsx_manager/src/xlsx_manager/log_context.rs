@@ -0,0 +1,33 @@
+use std::cell::RefCell;
+
+thread_local! {
+    static JOB_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with `job_id` attached to the current thread, so that a logger
+/// can add it to every line logged meanwhile through [`current_job_id`]. The
+/// files of a batch processed in parallel inherit it on their own threads.
+pub fn with_job_id<R>(job_id: &str, f: impl FnOnce() -> R) -> R {
+    scoped(Some(job_id.to_string()), f)
+}
+
+/// Job id set by [`with_job_id`] on the current thread.
+pub fn current_job_id() -> Option<String> {
+    JOB_ID.with(|job_id| job_id.borrow().clone())
+}
+
+/// Runs `f` with `job_id` attached to the current thread, restoring the
+/// previous one afterwards, even if `f` panics.
+pub(crate) fn scoped<R>(job_id: Option<String>, f: impl FnOnce() -> R) -> R {
+    let _restore = Restore(JOB_ID.replace(job_id));
+    f()
+}
+
+// Puts back the job id a scope replaced when dropped.
+struct Restore(Option<String>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        JOB_ID.set(self.0.take());
+    }
+}
@@ -0,0 +1,217 @@
+use crate::error::{Error, Result};
+use crate::xlsx_manager::xlsx_manager::CancelFlag;
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+use zip::ZipArchive;
+
+/// Memory assumed for a streamed workbook besides its shared strings: read
+/// buffers and the row kept by the constant-memory writer.
+const STREAMING_OVERHEAD: u64 = 16 * 1024 * 1024;
+/// Ratio between the memory of a fully loaded sheet and the size of its
+/// file. Loaded cells take 32 bytes each, empty ones included.
+const IN_MEMORY_FACTOR: u64 = 10;
+/// How often a file waiting for memory checks whether the batch was
+/// cancelled.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Memory shared by the files being processed. A file only starts once its
+/// estimated memory fits in what the running files leave free, so the budget
+/// also bounds how many files run at once. Clones share the same budget.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBudget {
+    // Bytes available, `0` for no limit
+    limit: u64,
+    // Bytes reserved by the running files
+    used: Arc<(Mutex<u64>, Condvar)>,
+}
+
+impl MemoryBudget {
+    /// Creates a budget of `limit` bytes, `0` for no limit.
+    pub fn new(limit: u64) -> Self {
+        MemoryBudget { limit, used: Arc::default() }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Reserves the memory needed to process the workbook at `path`, see
+    /// [`MemoryBudget::reserve`]. An unlimited budget never waits.
+    pub(crate) fn reserve_file(
+        &self,
+        path: &Path,
+        cancel: &CancelFlag,
+    ) -> Result<Reservation<'_>> {
+        if self.limit == 0 {
+            return Ok(Reservation { budget: self, bytes: 0 });
+        }
+        self.reserve(estimate_memory(path), cancel)
+    }
+
+    /// Waits until `bytes` are free and reserves them until the returned
+    /// guard is dropped. A file needing more than the whole budget waits
+    /// until it is the only one running. Returns [`Error::Cancelled`] if
+    /// `cancel` is set while waiting.
+    fn reserve(
+        &self,
+        bytes: u64,
+        cancel: &CancelFlag,
+    ) -> Result<Reservation<'_>> {
+        let bytes = bytes.min(self.limit);
+        let (used, freed) = &*self.used;
+        let mut used = used.lock().unwrap_or_else(|e| e.into_inner());
+        while *used > 0 && *used + bytes > self.limit {
+            if cancel.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            used = freed
+                .wait_timeout(used, CANCEL_POLL_INTERVAL)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        *used += bytes;
+        Ok(Reservation { budget: self, bytes })
+    }
+}
+
+/// Memory reserved from a [`MemoryBudget`], given back when dropped.
+pub(crate) struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if self.bytes == 0 {
+            return;
+        }
+        let (used, freed) = &*self.budget.used;
+        let mut used = used.lock().unwrap_or_else(|e| e.into_inner());
+        *used -= self.bytes;
+        freed.notify_all();
+    }
+}
+
+// Estimates the memory needed to process the workbook at `path`. `.xlsx`
+// files are streamed and only hold their shared strings in memory; other
+// formats are loaded whole.
+fn estimate_memory(path: &Path) -> u64 {
+    let file_size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if path.extension().and_then(|s| s.to_str()) != Some("xlsx") {
+        return file_size.saturating_mul(IN_MEMORY_FACTOR);
+    }
+    shared_strings_size(path)
+        .unwrap_or(file_size)
+        .saturating_add(STREAMING_OVERHEAD)
+}
+
+// Uncompressed size of the shared strings table of an .xlsx file.
+fn shared_strings_size(path: &Path) -> Option<u64> {
+    let mut archive = ZipArchive::new(File::open(path).ok()?).ok()?;
+    let size = match archive.by_name("xl/sharedStrings.xml") {
+        Ok(entry) => entry.size(),
+        Err(_) => 0,
+    };
+    Some(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xlsx_manager::test_workbook::write_xlsx;
+    use calamine::Data;
+    use std::io::Write;
+    use std::sync::mpsc;
+    use std::thread;
+    use uuid::Uuid;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    // Long enough for a thread that could reserve to have done so
+    const SETTLE: Duration = Duration::from_millis(300);
+
+    #[test]
+    fn reserve_waits_until_memory_is_released() {
+        let budget = MemoryBudget::new(100);
+        let cancel = CancelFlag::new();
+        let first = budget.reserve(60, &cancel).unwrap();
+
+        let (reserved, waiting) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let _second = budget.reserve(60, &cancel).unwrap();
+                reserved.send(()).unwrap();
+            });
+            assert!(waiting.recv_timeout(SETTLE).is_err());
+
+            drop(first);
+            waiting.recv_timeout(Duration::from_secs(10)).unwrap();
+        });
+        // Both reservations were given back
+        let _all = budget.reserve(100, &cancel).unwrap();
+    }
+
+    #[test]
+    fn file_over_the_budget_runs_alone() {
+        let budget = MemoryBudget::new(100);
+        let cancel = CancelFlag::new();
+        // Nothing else runs, so it starts at once instead of waiting forever
+        let oversized = budget.reserve(1_000, &cancel).unwrap();
+
+        let (reserved, waiting) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let _small = budget.reserve(1, &cancel).unwrap();
+                reserved.send(()).unwrap();
+            });
+            assert!(waiting.recv_timeout(SETTLE).is_err());
+
+            drop(oversized);
+            waiting.recv_timeout(Duration::from_secs(10)).unwrap();
+        });
+    }
+
+    #[test]
+    fn cancelling_stops_the_wait() {
+        let budget = MemoryBudget::new(100);
+        let cancel = CancelFlag::new();
+        let _running = budget.reserve(100, &CancelFlag::new()).unwrap();
+
+        cancel.cancel();
+        assert!(matches!(budget.reserve(1, &cancel), Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn estimate_follows_the_format() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).unwrap();
+
+        // Loaded formats take a multiple of their size
+        let xls = dir.join("a.xls");
+        fs::write(&xls, vec![0; 1_000]).unwrap();
+        assert_eq!(estimate_memory(&xls), 1_000 * IN_MEMORY_FACTOR);
+
+        // Streamed workbooks only hold their shared strings
+        let inline = dir.join("inline.xlsx");
+        write_xlsx(&inline, &[("Sheet1", vec![vec![Data::Float(1.0)]])]);
+        assert_eq!(estimate_memory(&inline), STREAMING_OVERHEAD);
+
+        let shared = dir.join("shared.xlsx");
+        let mut zip = ZipWriter::new(File::create(&shared).unwrap());
+        let options: FileOptions<'_, ()> = FileOptions::default();
+        zip.start_file("xl/sharedStrings.xml", options).unwrap();
+        zip.write_all(&vec![b' '; 5_000]).unwrap();
+        zip.finish().unwrap();
+        assert_eq!(estimate_memory(&shared), 5_000 + STREAMING_OVERHEAD);
+
+        // A file that is not a Zip file counts its own size
+        let broken = dir.join("broken.xlsx");
+        fs::write(&broken, vec![0; 2_000]).unwrap();
+        assert_eq!(estimate_memory(&broken), 2_000 + STREAMING_OVERHEAD);
+
+        assert_eq!(estimate_memory(&dir.join("missing.xls")), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
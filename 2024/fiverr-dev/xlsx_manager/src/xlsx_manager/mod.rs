@@ -1,2 +1,29 @@
 mod file_ops;
-pub mod xlsx_manager;
+mod header_detection;
+mod log_context;
+mod memory;
+mod preview;
+#[cfg(test)]
+pub(crate) mod test_workbook;
+#[allow(clippy::module_inception)]
+mod xlsx_manager;
+mod zip_ops;
+
+pub use file_ops::list_excel_files_in_directory;
+pub use header_detection::detect_header_rows;
+pub use log_context::{current_job_id, with_job_id};
+pub use memory::MemoryBudget;
+pub use preview::{
+    preview_workbook, PreviewCell, SheetPreview, WorkbookPreview,
+};
+pub use xlsx_manager::{
+    excel_serial_to_naive_datetime, process_excel_files_parallel,
+    process_excel_files_with_progress, process_input_folder,
+    process_input_folder_with_progress, process_single_excel, CancelFlag,
+    FileFailure, FileOverride, ProcessOptions, ProcessingSummary,
+    ProgressEvent,
+};
+pub use zip_ops::{
+    create_zip_at, create_zip_from_folder, extract_zip_file,
+    extract_zip_file_to, ZipLimits,
+};
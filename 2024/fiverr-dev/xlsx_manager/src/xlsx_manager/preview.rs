@@ -0,0 +1,333 @@
+use crate::error::{Error, Result};
+use crate::xlsx_manager::header_detection::{
+    detect_header_rows, DETECTION_ROWS, SCAN_ROWS,
+};
+use crate::xlsx_manager::xlsx_manager::{
+    excel_serial_to_naive_datetime, ProcessOptions,
+};
+use calamine::{
+    open_workbook_auto, Cell, Data, DataRef, Range, Reader, SheetType, Sheets,
+    Xlsx, XlsxError,
+};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// A cell value, tagged with the calamine [`Data`] variant it was read as.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum PreviewCell {
+    Empty,
+    String(String),
+    Float(f64),
+    Int(i64),
+    Bool(bool),
+    /// Excel date serial, converted to a date and time
+    DateTime(NaiveDateTime),
+    DateTimeIso(String),
+    DurationIso(String),
+    /// Cell error such as `#DIV/0!`, or a date serial that cannot be converted
+    Error(String),
+}
+
+/// The first rows of one sheet.
+#[derive(Debug, Clone, Serialize)]
+pub struct SheetPreview {
+    pub name: String,
+    /// Number of rows and columns of the whole sheet. For .xlsx sheets longer
+    /// than what is previewed, they come from the size the sheet declares,
+    /// and are `None` if it declares none.
+    pub total_rows: Option<usize>,
+    pub total_columns: Option<usize>,
+    pub rows: Vec<Vec<PreviewCell>>,
+}
+
+/// Preview of a workbook before and after processing.
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkbookPreview {
+    /// Every worksheet of the workbook as it was uploaded
+    pub sheets: Vec<SheetPreview>,
+    /// Number of banner rows [`detect_header_rows`] finds above the table
+    /// of the first sheet, a suggestion for `delete_first_n_rows`
+    pub detected_header_rows: usize,
+    /// Number of rows deleted from the first sheet with the given options
+    pub rows_deleted: usize,
+    /// The sheet [`process_single_excel`](crate::process_single_excel) would
+    /// write with the given options
+    pub result: SheetPreview,
+}
+
+/// Reads the first `max_rows` rows of every worksheet of a workbook, along
+/// with the same rows of the processed output of the first sheet for
+/// `options`. Only the rows needed for that are read from .xlsx files; the
+/// sheets of other formats are read whole, one at a time. Waits for
+/// `options.memory_budget` to leave room for the file first.
+pub fn preview_workbook(
+    path: &Path,
+    options: &ProcessOptions,
+    max_rows: usize,
+) -> Result<WorkbookPreview> {
+    let _memory = options.memory_budget.reserve_file(path, &options.cancel)?;
+    let unreadable =
+        |source| Error::UnreadableWorkbook { path: path.to_path_buf(), source };
+    let mut workbook = open_workbook_auto(path).map_err(unreadable)?;
+    if workbook.sheet_names().is_empty() {
+        return Err(Error::MissingSheet { path: path.to_path_buf(), index: 0 });
+    }
+    // Processing keeps the first sheet whatever its type; chart and macro
+    // sheets after it have no cells to show
+    let names: Vec<String> = workbook
+        .sheets_metadata()
+        .iter()
+        .enumerate()
+        .filter(|(index, sheet)| {
+            *index == 0 || sheet.typ == SheetType::WorkSheet
+        })
+        .map(|(_, sheet)| sheet.name.clone())
+        .collect();
+
+    let mut sheets = Vec::with_capacity(names.len());
+    let mut first = None;
+    for (index, name) in names.iter().enumerate() {
+        // The first sheet also needs the rows its result skips
+        let rows =
+            if index == 0 { head_rows(options, max_rows) } else { max_rows };
+        let head = match &mut workbook {
+            Sheets::Xlsx(xlsx) => stream_head(xlsx, name, rows)
+                .map_err(|source| unreadable(source.into()))?,
+            workbook => {
+                let range =
+                    workbook.worksheet_range(name).map_err(unreadable)?;
+                let (height, width) = range.get_size();
+                SheetHead {
+                    range,
+                    total_rows: Some(height),
+                    total_columns: Some(width),
+                }
+            }
+        };
+        sheets.push(sheet_preview(name, &head, 0, max_rows, input_cell));
+        if index == 0 {
+            first = Some(head);
+        }
+    }
+
+    let head = first.expect("the workbook has a first sheet");
+    let detected_header_rows = detect_header_rows(&head.range);
+    let rows_deleted = options.rows_to_delete(&head.range);
+    let result =
+        sheet_preview(&names[0], &head, rows_deleted, max_rows, output_cell);
+
+    Ok(WorkbookPreview { sheets, detected_header_rows, rows_deleted, result })
+}
+
+// The first rows of a sheet and the size of the whole sheet.
+struct SheetHead {
+    range: Range<Data>,
+    total_rows: Option<usize>,
+    total_columns: Option<usize>,
+}
+
+// Number of leading rows a preview needs: enough to detect the banner rows,
+// and to show `max_rows` rows both before and after deleting them.
+fn head_rows(options: &ProcessOptions, max_rows: usize) -> usize {
+    let deleted = if options.detect_header_rows {
+        SCAN_ROWS
+    } else {
+        options.delete_first_n_rows
+    };
+    deleted.saturating_add(max_rows).max(DETECTION_ROWS)
+}
+
+// Reads the first `max_rows` rows of an .xlsx sheet, counted from its first
+// non-empty row, and stops there.
+fn stream_head(
+    xlsx: &mut Xlsx<BufReader<File>>,
+    sheet_name: &str,
+    max_rows: usize,
+) -> std::result::Result<SheetHead, XlsxError> {
+    let mut cells = xlsx.worksheet_cells_reader(sheet_name)?;
+    let declared = cells.dimensions();
+    let mut head = Vec::new();
+    let mut first_row = None;
+    // First row left unread
+    let mut next_row = None;
+    while let Some(cell) = cells.next_cell()? {
+        if *cell.get_value() == DataRef::Empty {
+            continue;
+        }
+        let (row, col) = cell.get_position();
+        let first_row = *first_row.get_or_insert(row);
+        if (row - first_row) as usize >= max_rows {
+            next_row = Some(row);
+            break;
+        }
+        head.push(Cell::new((row, col), cell.get_value().clone().into()));
+    }
+
+    let range = Range::from_sparse(head);
+    let (height, width) = range.get_size();
+    let (total_rows, total_columns) = match next_row {
+        None => (Some(height), Some(width)),
+        // The rest of the sheet is sized from the dimension it declares, if
+        // that covers the rows left unread
+        Some(next_row) if declared.end.0 >= next_row => {
+            let (start_row, start_col) = range.start().unwrap_or_default();
+            let rows = (declared.end.0 + 1 - start_row) as usize;
+            let columns = (declared.end.1 + 1).saturating_sub(start_col);
+            (Some(rows.max(height)), Some(width.max(columns as usize)))
+        }
+        Some(_) => (None, None),
+    };
+    Ok(SheetHead { range, total_rows, total_columns })
+}
+
+// Converts the rows of a sheet after the first `skip_rows` ones, up to
+// `max_rows` of them.
+fn sheet_preview(
+    name: &str,
+    head: &SheetHead,
+    skip_rows: usize,
+    max_rows: usize,
+    convert: impl Fn(&Data) -> PreviewCell,
+) -> SheetPreview {
+    let rows = head
+        .range
+        .rows()
+        .skip(skip_rows)
+        .take(max_rows)
+        .map(|row| row.iter().map(&convert).collect())
+        .collect();
+    SheetPreview {
+        name: name.to_string(),
+        total_rows: head
+            .total_rows
+            .map(|total_rows| total_rows.saturating_sub(skip_rows)),
+        total_columns: head.total_columns,
+        rows,
+    }
+}
+
+// A cell as it was read.
+fn input_cell(cell: &Data) -> PreviewCell {
+    match cell {
+        Data::Empty => PreviewCell::Empty,
+        Data::String(s) => PreviewCell::String(s.clone()),
+        Data::Float(f) => PreviewCell::Float(*f),
+        Data::Int(i) => PreviewCell::Int(*i),
+        Data::Bool(b) => PreviewCell::Bool(*b),
+        Data::DateTime(excel_dt) => date_time_cell(excel_dt.as_f64()),
+        Data::DateTimeIso(s) => PreviewCell::DateTimeIso(s.clone()),
+        Data::DurationIso(s) => PreviewCell::DurationIso(s.clone()),
+        Data::Error(err) => PreviewCell::Error(err.to_string()),
+    }
+}
+
+// A cell as `process_cell` writes it: integers become numbers, errors are
+// blanked and ISO dates and durations are dropped.
+fn output_cell(cell: &Data) -> PreviewCell {
+    match cell {
+        Data::Int(i) => PreviewCell::Float(*i as f64),
+        Data::Error(_) | Data::DateTimeIso(_) | Data::DurationIso(_) => {
+            PreviewCell::Empty
+        }
+        _ => input_cell(cell),
+    }
+}
+
+fn date_time_cell(serial: f64) -> PreviewCell {
+    match excel_serial_to_naive_datetime(serial) {
+        Some(date_time) => PreviewCell::DateTime(date_time),
+        None => PreviewCell::Error(format!("Invalid date serial {}", serial)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xlsx_manager::test_workbook::write_xlsx;
+
+    fn string(s: &str) -> Data {
+        Data::String(s.to_string())
+    }
+
+    fn rows(count: usize) -> Vec<Vec<Data>> {
+        (0..count)
+            .map(|i| vec![string(&format!("row {}", i)), Data::Float(i as f64)])
+            .collect()
+    }
+
+    fn preview(
+        sheets: &[(&str, Vec<Vec<Data>>)],
+        options: &ProcessOptions,
+        max_rows: usize,
+    ) -> WorkbookPreview {
+        let path =
+            std::env::temp_dir().join(format!("{}.xlsx", uuid::Uuid::new_v4()));
+        write_xlsx(&path, sheets);
+        let preview = preview_workbook(&path, options, max_rows);
+        std::fs::remove_file(&path).unwrap();
+        preview.unwrap()
+    }
+
+    #[test]
+    fn previews_every_sheet_up_to_max_rows() {
+        let preview = preview(
+            &[("First", rows(120)), ("Second", rows(3)), ("Third", rows(80))],
+            &ProcessOptions::default(),
+            50,
+        );
+
+        let names: Vec<_> =
+            preview.sheets.iter().map(|sheet| sheet.name.as_str()).collect();
+        assert_eq!(names, ["First", "Second", "Third"]);
+        let lengths: Vec<_> =
+            preview.sheets.iter().map(|sheet| sheet.rows.len()).collect();
+        assert_eq!(lengths, [50, 3, 50]);
+        assert_eq!(preview.sheets[1].total_rows, Some(3));
+        assert_eq!(
+            preview.sheets[2].rows[49][0],
+            PreviewCell::String("row 49".to_string())
+        );
+        // The test workbooks declare no size, so longer sheets have none
+        assert_eq!(preview.sheets[0].total_rows, None);
+    }
+
+    #[test]
+    fn result_skips_the_deleted_rows_of_the_first_sheet() {
+        let options =
+            ProcessOptions { delete_first_n_rows: 60, ..Default::default() };
+        let preview =
+            preview(&[("First", rows(120)), ("Second", rows(3))], &options, 50);
+
+        assert_eq!(preview.rows_deleted, 60);
+        assert_eq!(preview.result.rows.len(), 50);
+        assert_eq!(
+            preview.result.rows[0][0],
+            PreviewCell::String("row 60".to_string())
+        );
+        // Numbers are written as floats, as processing does
+        assert_eq!(preview.result.rows[0][1], PreviewCell::Float(60.0));
+        assert_eq!(preview.sheets[0].rows.len(), 50);
+    }
+
+    #[test]
+    fn detects_banner_rows_of_the_first_sheet() {
+        let mut first = vec![vec![string("Monthly report")], vec![]];
+        first.push(vec![string("Name"), string("Amount")]);
+        first.extend(rows(5));
+        let options =
+            ProcessOptions { detect_header_rows: true, ..Default::default() };
+        let preview = preview(&[("First", first)], &options, 50);
+
+        assert_eq!(preview.detected_header_rows, 2);
+        assert_eq!(preview.rows_deleted, 2);
+        assert_eq!(
+            preview.result.rows[0][0],
+            PreviewCell::String("Name".to_string())
+        );
+        assert_eq!(preview.result.total_rows, Some(6));
+    }
+}
@@ -0,0 +1,144 @@
+//! Minimal .xlsx files for tests, written without a workbook writer.
+
+use calamine::Data;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+const MAIN_NS: &str =
+    "http://schemas.openxmlformats.org/spreadsheetml/2006/main";
+const RELS_NS: &str =
+    "http://schemas.openxmlformats.org/package/2006/relationships";
+const DOC_RELS_NS: &str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+
+/// Writes a workbook with one worksheet per `(name, rows)`. Strings are
+/// written inline, numbers and booleans as values, and empty cells are left
+/// out.
+pub(crate) fn write_xlsx(path: &Path, sheets: &[(&str, Vec<Vec<Data>>)]) {
+    let mut zip = ZipWriter::new(File::create(path).unwrap());
+    let mut add = |name: &str, content: String| {
+        let options: FileOptions<'_, ()> = FileOptions::default();
+        zip.start_file(name, options).unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    };
+
+    let overrides: String = (1..=sheets.len())
+        .map(|n| {
+            format!(
+                "<Override PartName=\"/xl/worksheets/sheet{}.xml\" \
+                 ContentType=\"application/vnd.openxmlformats-officedocument.\
+                 spreadsheetml.worksheet+xml\"/>",
+                n
+            )
+        })
+        .collect();
+    add(
+        "[Content_Types].xml",
+        format!(
+            "<?xml version=\"1.0\"?><Types xmlns=\"http://schemas.\
+             openxmlformats.org/package/2006/content-types\"><Default \
+             Extension=\"rels\" ContentType=\"application/vnd.openxmlformats\
+             -package.relationships+xml\"/><Override PartName=\"/xl/\
+             workbook.xml\" ContentType=\"application/vnd.openxmlformats-\
+             officedocument.spreadsheetml.sheet.main+xml\"/>{}</Types>",
+            overrides
+        ),
+    );
+    add(
+        "_rels/.rels",
+        format!(
+            "<?xml version=\"1.0\"?><Relationships xmlns=\"{}\">\
+             <Relationship Id=\"rId1\" Type=\"{}/officeDocument\" \
+             Target=\"xl/workbook.xml\"/></Relationships>",
+            RELS_NS, DOC_RELS_NS
+        ),
+    );
+
+    let mut entries = String::new();
+    let mut relationships = String::new();
+    for (index, (name, _)) in sheets.iter().enumerate() {
+        let n = index + 1;
+        entries.push_str(&format!(
+            "<sheet name=\"{}\" sheetId=\"{}\" r:id=\"rId{}\"/>",
+            name, n, n
+        ));
+        relationships.push_str(&format!(
+            "<Relationship Id=\"rId{}\" Type=\"{}/worksheet\" \
+             Target=\"worksheets/sheet{}.xml\"/>",
+            n, DOC_RELS_NS, n
+        ));
+    }
+    add(
+        "xl/workbook.xml",
+        format!(
+            "<?xml version=\"1.0\"?><workbook xmlns=\"{}\" xmlns:r=\"{}\">\
+             <sheets>{}</sheets></workbook>",
+            MAIN_NS, DOC_RELS_NS, entries
+        ),
+    );
+    add(
+        "xl/_rels/workbook.xml.rels",
+        format!(
+            "<?xml version=\"1.0\"?><Relationships xmlns=\"{}\">{}\
+             </Relationships>",
+            RELS_NS, relationships
+        ),
+    );
+
+    for (index, (_, rows)) in sheets.iter().enumerate() {
+        add(&format!("xl/worksheets/sheet{}.xml", index + 1), sheet_xml(rows));
+    }
+    zip.finish().unwrap();
+}
+
+fn sheet_xml(rows: &[Vec<Data>]) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\"?><worksheet xmlns=\"{}\"><sheetData>",
+        MAIN_NS
+    );
+    for (row_idx, row) in rows.iter().enumerate() {
+        xml.push_str(&format!("<row r=\"{}\">", row_idx + 1));
+        for (col_idx, cell) in row.iter().enumerate() {
+            let reference = format!("{}{}", column_name(col_idx), row_idx + 1);
+            let cell = match cell {
+                Data::Empty => continue,
+                Data::String(s) => format!(
+                    "<c r=\"{}\" t=\"inlineStr\"><is><t>{}</t></is></c>",
+                    reference, s
+                ),
+                Data::Float(f) => {
+                    format!("<c r=\"{}\"><v>{}</v></c>", reference, f)
+                }
+                Data::Int(i) => {
+                    format!("<c r=\"{}\"><v>{}</v></c>", reference, i)
+                }
+                Data::Bool(b) => format!(
+                    "<c r=\"{}\" t=\"b\"><v>{}</v></c>",
+                    reference, *b as u8
+                ),
+                other => panic!("unsupported test cell {:?}", other),
+            };
+            xml.push_str(&cell);
+        }
+        xml.push_str("</row>");
+    }
+    xml.push_str("</sheetData></worksheet>");
+    xml
+}
+
+// `A`, `B`, ..., `Z`, `AA`, ...
+fn column_name(mut index: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'A' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
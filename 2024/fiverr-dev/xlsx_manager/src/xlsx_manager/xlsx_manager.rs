@@ -1,158 +1,931 @@
+use crate::error::{Error, Result};
 use crate::xlsx_manager::file_ops::{
-    create_directory_if_missing, generate_output_file_path, has_excel_extension,
+    create_directory_if_missing, generate_output_file_path,
+    has_excel_extension, list_excel_files_in_directory, output_file_name,
+};
+use crate::xlsx_manager::header_detection::{
+    detect_header_rows, DETECTION_ROWS,
+};
+use crate::xlsx_manager::log_context;
+use crate::xlsx_manager::memory::MemoryBudget;
+use crate::xlsx_manager::zip_ops::{extract_zip_file_to, ZipLimits};
+use calamine::{
+    open_workbook_auto, Cell, Data, DataRef, Range, Reader, Sheets, Xlsx,
 };
-use calamine::{open_workbook_auto, Data, Reader};
 use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Timelike};
+use glob::{MatchOptions, Pattern};
 use log::{error, info};
 use rayon::prelude::*;
-use std::path::Path;
-use std::{error, thread};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
 use xlsxwriter::{Format, Worksheet};
 
+/// Largest number of rows an .xlsx worksheet can hold.
+const MAX_XLSX_ROWS: usize = 1_048_576;
+/// Largest number of columns an .xlsx worksheet can hold.
+const MAX_XLSX_COLUMNS: usize = 16_384;
+
+/// Options controlling how each workbook is transformed.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessOptions {
+    /// Number of rows to delete from the top of the first sheet
+    pub delete_first_n_rows: usize,
+    /// Detect the banner rows of each file with [`detect_header_rows`] and
+    /// delete those instead of `delete_first_n_rows`
+    pub detect_header_rows: bool,
+    /// Options for specific files; the first matching override applies
+    pub overrides: Vec<FileOverride>,
+    /// Stops the batch when cancelled
+    pub cancel: CancelFlag,
+    /// Memory the files of the batch may use at once
+    pub memory_budget: MemoryBudget,
+    /// Bounds on what each Zip file of the batch may extract
+    pub zip_limits: ZipLimits,
+}
+
+/// Shared flag asking a running batch to stop. Files that are not started
+/// yet are skipped, and the batch returns [`Error::Cancelled`].
+#[derive(Debug, Clone, Default)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    // Returns `Error::Cancelled` once the flag is set.
+    fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Options replacing the batch defaults for files whose name matches a
+/// pattern.
+#[derive(Debug, Clone)]
+pub struct FileOverride {
+    pattern: Pattern,
+    /// Number of rows to delete, which also turns off banner detection
+    pub delete_first_n_rows: Option<usize>,
+    pub detect_header_rows: Option<bool>,
+}
+
+impl FileOverride {
+    /// Creates an override for the files named `pattern`, which is either a
+    /// file name or a glob pattern such as `sales_*.xlsx`.
+    pub fn new(pattern: &str) -> Result<Self> {
+        let pattern = Pattern::new(pattern).map_err(|source| {
+            Error::InvalidPattern { pattern: pattern.to_string(), source }
+        })?;
+        Ok(FileOverride {
+            pattern,
+            delete_first_n_rows: None,
+            detect_header_rows: None,
+        })
+    }
+
+    /// Checks whether the override applies to `file_name`. A path matches
+    /// if either the whole path or its last component does; wildcards do not
+    /// match `/`.
+    pub fn matches(&self, file_name: &str) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        let matches = |name: &str| {
+            self.pattern.as_str() == name
+                || self.pattern.matches_with(name, options)
+        };
+        matches(file_name)
+            || Path::new(file_name)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name != file_name && matches(name))
+    }
+}
+
+impl ProcessOptions {
+    /// Returns the options for one file, with the first override matching
+    /// its name applied.
+    pub fn for_file(&self, file_name: &str) -> ProcessOptions {
+        let mut options = ProcessOptions {
+            delete_first_n_rows: self.delete_first_n_rows,
+            detect_header_rows: self.detect_header_rows,
+            overrides: Vec::new(),
+            cancel: self.cancel.clone(),
+            memory_budget: self.memory_budget.clone(),
+            zip_limits: self.zip_limits,
+        };
+        if let Some(file_override) =
+            self.overrides.iter().find(|o| o.matches(file_name))
+        {
+            if let Some(rows) = file_override.delete_first_n_rows {
+                options.delete_first_n_rows = rows;
+                options.detect_header_rows = false;
+            }
+            if let Some(detect) = file_override.detect_header_rows {
+                options.detect_header_rows = detect;
+            }
+        }
+        options
+    }
+
+    /// Returns the number of rows to delete from the top of `range`.
+    pub fn rows_to_delete(&self, range: &Range<Data>) -> usize {
+        if self.detect_header_rows {
+            detect_header_rows(range)
+        } else {
+            self.delete_first_n_rows
+        }
+    }
+}
+
+/// A file of a batch that could not be processed.
+#[derive(Debug)]
+pub struct FileFailure {
+    pub file: String,
+    pub error: Error,
+}
+
+/// Outcome of a batch run: the files that were written and the files that
+/// failed, paired with the error explaining why.
+#[derive(Debug, Default)]
+pub struct ProcessingSummary {
+    pub processed: Vec<String>,
+    pub failed: Vec<FileFailure>,
+    /// Number of rows deleted from each processed file
+    pub rows_deleted: BTreeMap<String, usize>,
+}
+
+/// Progress of a batch, reported while it runs. Files are processed in
+/// parallel, so events of different files interleave.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// The files to process are known
+    Started {
+        total_files: usize,
+    },
+    FileStarted {
+        file: String,
+    },
+    FileFinished {
+        file: String,
+        rows_deleted: usize,
+        /// Rows of the output sheet
+        rows_written: usize,
+    },
+    /// A ZIP file was extracted, before `Started`
+    ZipExtracted {
+        file: String,
+        duration_ms: u64,
+    },
+    /// A workbook, or a ZIP file before `Started`, could not be processed
+    FileFailed {
+        file: String,
+        code: String,
+        message: String,
+    },
+}
+
+impl ProgressEvent {
+    // Reports a failed file, naming paths relative to the folder it was
+    // found in, as the events may be shown to remote clients.
+    fn failed(file: &str, error: &Error, folder: &Path) -> Self {
+        ProgressEvent::FileFailed {
+            file: file.to_string(),
+            code: error.code().to_string(),
+            message: error.message_relative_to(folder),
+        }
+    }
+}
+
 // Creates and returns a new Excel workbook for writing output.
 // The workbook is created at the specified `file_path`.
 // The function returns the new workbook or an error if the creation fails.
-pub fn create_new_workbook(
-    file_path: &Path,
-) -> Result<xlsxwriter::Workbook, Box<dyn error::Error>> {
-    Ok(xlsxwriter::Workbook::new(file_path.to_str().unwrap())?)
+fn create_new_workbook(file_path: &Path) -> Result<xlsxwriter::Workbook> {
+    let file_path = file_path.to_str().ok_or_else(|| {
+        Error::InvalidFilePath(format!(
+            "Invalid UTF-8 sequence in path: {:?}",
+            file_path
+        ))
+    })?;
+    // Rows are written in order, so each one can be flushed to disk as soon
+    // as the next one starts
+    xlsxwriter::Workbook::new_with_options(file_path, true, None, false)
+        .map_err(|source| Error::WorkbookWrite {
+            path: file_path.into(),
+            source,
+        })
 }
 
-// Processes all Excel files in the given `source_folder` and saves the processed
-// files to the `target_folder`. Files are processed in parallel using the `rayon` crate.
+/// Processes the Excel files found directly in `input_folder` and inside its
+/// ZIP files into `target_folder`. Each ZIP file is extracted into its own
+/// folder of `extract_folder`, so `input_folder` is never modified. ZIP files
+/// that cannot be extracted, and files whose output has the same name as
+/// that of an earlier file, such as `a.xls` next to `a.xlsx`, are reported
+/// in the summary like failing workbooks.
+/// Returns [`Error::NoExcelFiles`] if there is nothing to process.
+pub fn process_input_folder(
+    input_folder: &Path,
+    extract_folder: &Path,
+    target_folder: &Path,
+    options: &ProcessOptions,
+) -> Result<ProcessingSummary> {
+    process_input_folder_with_progress(
+        input_folder,
+        extract_folder,
+        target_folder,
+        options,
+        &|_| {},
+    )
+}
+
+/// Same as [`process_input_folder`], calling `progress` as the batch
+/// advances.
+pub fn process_input_folder_with_progress(
+    input_folder: &Path,
+    extract_folder: &Path,
+    target_folder: &Path,
+    options: &ProcessOptions,
+    progress: &(dyn Fn(ProgressEvent) + Sync),
+) -> Result<ProcessingSummary> {
+    create_directory_if_missing(extract_folder)?;
+    let mut summary = ProcessingSummary::default();
+
+    // Extract every ZIP file of the input folder into a folder of its own,
+    // so that members with the same name do not overwrite each other
+    let mut zip_folders = Vec::new();
+    for entry in fs::read_dir(input_folder).map_err(Error::io(input_folder))? {
+        let path = entry.map_err(Error::io(input_folder))?.path();
+        if path.extension().and_then(|s| s.to_str()) != Some("zip") {
+            continue;
+        }
+        options.cancel.check()?;
+        let file = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let extraction_start = Instant::now();
+        let zip_folder = extract_folder.join(&file);
+        match extract_zip_file_to(&path, &zip_folder, &options.zip_limits) {
+            Ok(()) => {
+                progress(ProgressEvent::ZipExtracted {
+                    file: file.clone(),
+                    duration_ms: extraction_start.elapsed().as_millis() as u64,
+                });
+                zip_folders.push((file, zip_folder));
+            }
+            Err(error) => {
+                error!("Failed to extract '{}': {}", path.display(), error);
+                progress(ProgressEvent::failed(&file, &error, input_folder));
+                summary.failed.push(FileFailure { file, error });
+            }
+        }
+    }
+
+    // Process the Excel files found directly in the input folder and those
+    // extracted from ZIP files
+    zip_folders.sort();
+    let mut batches = Vec::new();
+    let sources = std::iter::once((None, input_folder)).chain(
+        zip_folders
+            .iter()
+            .map(|(zip, folder)| (Some(zip.as_str()), folder.as_path())),
+    );
+    for (zip, source_folder) in sources {
+        match list_excel_files_in_directory(source_folder) {
+            Ok(mut files) => {
+                files.sort();
+                batches.push((zip, source_folder, files));
+            }
+            Err(Error::NoExcelFiles(_)) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    options.cancel.check()?;
+
+    // The first file to claim an output name keeps it; the others fail
+    // rather than overwrite its output
+    let mut outputs: HashMap<String, String> = HashMap::new();
+    for (zip, source_folder, files) in &mut batches {
+        let mut kept = Vec::new();
+        for file in files.drain(..) {
+            let label = match zip {
+                Some(zip) => format!("{}/{}", zip, file),
+                None => file.clone(),
+            };
+            let output = output_file_name(&source_folder.join(&file))?;
+            match outputs.get(&output) {
+                Some(other) => {
+                    let error = Error::DuplicateOutput {
+                        file: label.clone(),
+                        other: other.clone(),
+                        output,
+                    };
+                    error!("Skipping '{}': {}", label, error);
+                    progress(ProgressEvent::failed(
+                        &label,
+                        &error,
+                        source_folder,
+                    ));
+                    summary.failed.push(FileFailure { file: label, error });
+                }
+                None => {
+                    outputs.insert(output, label);
+                    kept.push(file);
+                }
+            }
+        }
+        *files = kept;
+    }
+    let total_files = batches.iter().map(|(_, _, files)| files.len()).sum();
+    progress(ProgressEvent::Started { total_files });
+
+    for (_, source_folder, files) in batches {
+        let batch = process_batch(
+            source_folder,
+            target_folder,
+            &files,
+            options,
+            progress,
+        )?;
+        summary.processed.extend(batch.processed);
+        summary.failed.extend(batch.failed);
+        summary.rows_deleted.extend(batch.rows_deleted);
+    }
+
+    if summary.processed.is_empty() && summary.failed.is_empty() {
+        return Err(Error::NoExcelFiles(input_folder.to_path_buf()));
+    }
+    Ok(summary)
+}
+
+/// Processes all Excel files in the given `source_folder` and saves the processed
+/// files to the `target_folder`. Files are processed in parallel using the `rayon` crate.
+/// A failing file does not stop the batch; it is reported in the returned summary.
 pub fn process_excel_files_parallel(
-    source_folder: &str, // Path to the source folder containing Excel files
-    target_folder: &str, // Path to the target folder where processed files will be saved
-    files: &[String],    // Specific files to process
-    delete_first_n_rows: usize, // Delete first N rows
-) -> Result<(), Box<dyn error::Error>> {
+    source_folder: &Path, // Path to the source folder containing Excel files
+    target_folder: &Path, // Path to the target folder where processed files will be saved
+    files: &[String],     // Specific files to process
+    options: &ProcessOptions, // How each file is transformed
+) -> Result<ProcessingSummary> {
+    process_excel_files_with_progress(
+        source_folder,
+        target_folder,
+        files,
+        options,
+        &|_| {},
+    )
+}
+
+/// Same as [`process_excel_files_parallel`], calling `progress` as the batch
+/// advances. `progress` is called from the worker threads.
+pub fn process_excel_files_with_progress(
+    source_folder: &Path,
+    target_folder: &Path,
+    files: &[String],
+    options: &ProcessOptions,
+    progress: &(dyn Fn(ProgressEvent) + Sync),
+) -> Result<ProcessingSummary> {
+    progress(ProgressEvent::Started { total_files: files.len() });
+    process_batch(source_folder, target_folder, files, options, progress)
+}
+
+// Processes `files` in parallel, reporting each file to `progress`.
+fn process_batch(
+    source_folder: &Path,
+    target_folder: &Path,
+    files: &[String],
+    options: &ProcessOptions,
+    progress: &(dyn Fn(ProgressEvent) + Sync),
+) -> Result<ProcessingSummary> {
     // Ensure the target folder exists or create it
-    create_directory_if_missing(target_folder)
-        .expect("Error with target folder.");
+    create_directory_if_missing(target_folder)?;
 
-    info!("Folders\nSource: {}\nTarget: {}", source_folder, target_folder);
+    info!(
+        "Folders\nSource: {}\nTarget: {}",
+        source_folder.display(),
+        target_folder.display()
+    );
 
-    // Process files in parallel using Rayon
-    files.par_iter().for_each(|file| {
-        let source_file_path = Path::new(source_folder).join(file);
+    // Process files in parallel using Rayon, on threads that log with the
+    // job id of the caller
+    let job_id = log_context::current_job_id();
+    let results: Vec<(String, Result<ProcessedFile>)> = files
+        .par_iter()
+        .map(|file| {
+            let result = log_context::scoped(job_id.clone(), || {
+                process_batch_file(
+                    source_folder,
+                    target_folder,
+                    file,
+                    options,
+                    progress,
+                )
+            });
+            (file.clone(), result)
+        })
+        .collect();
+    options.cancel.check()?;
 
-        // Validate if the file is an Excel file
-        if !has_excel_extension(&source_file_path) {
-            error!("Skipping invalid excel file: {}", file);
-            return;
+    let mut summary = ProcessingSummary::default();
+    for (file, result) in results {
+        match result {
+            Ok(processed) => {
+                summary
+                    .rows_deleted
+                    .insert(file.clone(), processed.rows_deleted);
+                summary.processed.push(file);
+            }
+            Err(error) => summary.failed.push(FileFailure { file, error }),
         }
+    }
 
-        // Process the file and handle any errors
-        if let Err(err) = process_single_excel(
-            &source_file_path,
-            target_folder,
-            delete_first_n_rows,
-        ) {
+    Ok(summary)
+}
+
+// Processes one file of a batch, once the memory budget leaves room for it.
+fn process_batch_file(
+    source_folder: &Path,
+    target_folder: &Path,
+    file: &str,
+    options: &ProcessOptions,
+    progress: &(dyn Fn(ProgressEvent) + Sync),
+) -> Result<ProcessedFile> {
+    let source_file_path = source_folder.join(file);
+    if options.cancel.is_cancelled() {
+        return Err(Error::Cancelled);
+    }
+
+    // Validate if the file is an Excel file
+    if !has_excel_extension(&source_file_path) {
+        error!("Skipping invalid excel file: {}", file);
+        let error =
+            Error::InvalidFilePath(format!("Not an Excel file: {:?}", file));
+        progress(ProgressEvent::failed(file, &error, source_folder));
+        return Err(error);
+    }
+
+    // Wait until the memory budget leaves room for the file
+    let _memory = options
+        .memory_budget
+        .reserve_file(&source_file_path, &options.cancel)?;
+
+    // Process the file and handle any errors
+    progress(ProgressEvent::FileStarted { file: file.to_string() });
+    let file_options = options.for_file(file);
+    let result = process_excel(&source_file_path, target_folder, &file_options)
+        .inspect_err(|err| {
             error!(
                 "Error processing file {}: {}",
                 source_file_path.display(),
                 err
             );
-        }
-    });
+        });
+    match &result {
+        Ok(processed) => progress(ProgressEvent::FileFinished {
+            file: file.to_string(),
+            rows_deleted: processed.rows_deleted,
+            rows_written: processed.rows_written,
+        }),
+        Err(Error::Cancelled) => {}
+        Err(err) => progress(ProgressEvent::failed(file, err, source_folder)),
+    }
 
-    Ok(())
+    result
 }
 
-// Processes a single Excel file located at the given `path`, and writes the processed
-// data to a new file in the `target_folder`. It opens the Excel file, reads the data,
-// processes it, and saves it in the target folder with the same name.
+/// Processes a single Excel file located at the given `path`, and writes the processed
+/// data to a new file in the `target_folder`. It opens the Excel file, reads the data,
+/// processes it, and saves it in the target folder with the same name.
+/// Returns the path of the written file.
 pub fn process_single_excel(
-    source_file_path: &Path, // Path to the source Excel file
-    target_folder: &str, // Path to the target folder where the processed file will be saved
-    delete_first_n_rows: usize, // Delete first N rows
-) -> Result<(), Box<dyn error::Error>> {
+    source_file_path: &Path,  // Path to the source Excel file
+    target_folder: &Path, // Path to the target folder where the processed file will be saved
+    options: &ProcessOptions, // How the file is transformed
+) -> Result<PathBuf> {
+    process_excel(source_file_path, target_folder, options)
+        .map(|processed| processed.path)
+}
+
+// A workbook written by `process_excel`.
+struct ProcessedFile {
+    path: PathBuf,
+    rows_deleted: usize,
+    // Rows of the output sheet
+    rows_written: usize,
+}
+
+// Does the work of `process_single_excel`, also counting the rows deleted
+// from the file and written to the output.
+fn process_excel(
+    source_file_path: &Path,
+    target_folder: &Path,
+    options: &ProcessOptions,
+) -> Result<ProcessedFile> {
     info!(
         "Processing file: {}, {:?}",
         source_file_path.display(),
         thread::current().id()
     );
     // Open the Excel workbook automatically detecting its format
-    let mut workbook = open_workbook_auto(source_file_path)?;
+    let unreadable = |source| Error::UnreadableWorkbook {
+        path: source_file_path.to_path_buf(),
+        source,
+    };
+    let workbook = open_workbook_auto(source_file_path).map_err(unreadable)?;
     // Get the first sheet
     let sheet_names = workbook.sheet_names();
     if sheet_names.is_empty() {
-        return Err("No sheets found in the excel file.".into());
+        return Err(Error::MissingSheet {
+            path: source_file_path.to_path_buf(),
+            index: 0,
+        });
     }
-
     let first_sheet = &sheet_names[0];
-    // Create a format for date cells
-    let mut date_format = Format::new();
-    date_format.set_num_format("yyyy-mm-dd hh:mm:ss");
 
-    if let Some(Ok(range)) = workbook.worksheet_range_at(0) {
-        // Generate the path for the processed file
-        let target_file_path =
-            generate_output_file_path(source_file_path, target_folder)?;
+    // .xlsx sheets are streamed; the other formats can only be read whole
+    match workbook {
+        Sheets::Xlsx(mut xlsx) => stream_first_sheet(
+            &mut xlsx,
+            first_sheet,
+            source_file_path,
+            target_folder,
+            options,
+        ),
+        mut workbook => {
+            let range = match workbook.worksheet_range_at(0) {
+                Some(range) => range.map_err(unreadable)?,
+                None => {
+                    return Err(Error::MissingSheet {
+                        path: source_file_path.to_path_buf(),
+                        index: 0,
+                    })
+                }
+            };
+            write_first_sheet(
+                &range,
+                first_sheet,
+                source_file_path,
+                target_folder,
+                options,
+            )
+        }
+    }
+}
 
-        // Create a new Excel workbook for output
-        let workbook_out = create_new_workbook(&target_file_path)?;
+// Writes the rows of a loaded first sheet, less the rows to delete, to the
+// output workbook.
+fn write_first_sheet(
+    range: &Range<Data>,
+    sheet_name: &str,
+    source_file_path: &Path,
+    target_folder: &Path,
+    options: &ProcessOptions,
+) -> Result<ProcessedFile> {
+    // Stop before writing anything if the batch was cancelled while reading
+    options.cancel.check()?;
 
-        // Add a worksheet to the new workbook
-        let mut sheet = workbook_out.add_worksheet(Some(first_sheet))?;
+    let rows_to_delete = options.rows_to_delete(range);
+    log_detected_rows(options, rows_to_delete, source_file_path);
+    let (height, width) = range.get_size();
+    check_output_size(height, width, rows_to_delete)?;
 
-        process_rows(&range, &mut sheet, &date_format, delete_first_n_rows)?;
-        workbook_out.close()?;
-        info!("File processed and saved: {}", target_file_path.display());
-    } else {
-        return Err("Failed to read the first sheet.".into());
-    }
-    Ok(())
+    let path =
+        write_output(source_file_path, target_folder, sheet_name, |sheet| {
+            copy_range(range, rows_to_delete, |cell, row_idx, col_idx| {
+                sheet.write(cell, row_idx, col_idx)
+            })
+        })?;
+    Ok(ProcessedFile {
+        path,
+        rows_deleted: rows_to_delete,
+        rows_written: height.saturating_sub(rows_to_delete),
+    })
+}
+
+// Skips the first `rows_to_delete` rows (assumed to be headers) of a loaded
+// sheet and passes the other cells to `write`, in row order, with their
+// position in the output.
+fn copy_range(
+    range: &Range<Data>,
+    rows_to_delete: usize,
+    mut write: impl FnMut(&Data, u32, u16) -> Result<()>,
+) -> Result<()> {
+    let rows = range.rows().skip(rows_to_delete).enumerate();
+    rows.flat_map(|(row_idx, row)| {
+        row.iter()
+            .enumerate()
+            .map(move |(col_idx, cell)| (row_idx, col_idx, cell))
+    })
+    .try_for_each(|(row_idx, col_idx, cell)| {
+        write(cell, row_idx as u32, col_idx as u16)
+    })
 }
 
-// Processes rows and cells from the provided `range` (Excel data) and writes them
-// to the `sheet` in the new workbook. The first 7 rows are skipped (header rows),
-// and each cell is processed individually based on its type.
-pub fn process_rows(
-    range: &calamine::Range<Data>, // Data range from the source Excel file
-    sheet: &mut Worksheet,         // Worksheet to write the processed rows to
-    date_format: &Format,          // Format for date cells
-    del_first_n_rows: usize,
-) -> Result<(), Box<dyn error::Error>> {
-    // Skip the first N rows (assumed to be headers) and process the rest
-    for (row_idx, row) in range.rows().skip(del_first_n_rows).enumerate() {
-        // Process each cell in the row
-        for (col_idx, cell) in row.iter().enumerate() {
-            process_cell(
-                cell,
-                row_idx as u32,
-                col_idx as u16,
-                sheet,
-                date_format,
+// Bounds of the cells of a streamed sheet.
+struct SheetBounds {
+    start: (u32, u32),
+    end: (u32, u32),
+    // First rows of the sheet, for banner detection
+    head: Range<Data>,
+}
+
+// Streams the first sheet of an .xlsx workbook to the output workbook,
+// holding a single row in memory. The sheet is read twice: once for the
+// bounds of its cells and its first rows, which decide what is deleted, and
+// once to copy the remaining rows.
+fn stream_first_sheet(
+    xlsx: &mut Xlsx<BufReader<File>>,
+    sheet_name: &str,
+    source_file_path: &Path,
+    target_folder: &Path,
+    options: &ProcessOptions,
+) -> Result<ProcessedFile> {
+    // First pass: find where the cells start and end
+    let bounds = match read_bounds(xlsx, sheet_name, source_file_path, options)?
+    {
+        Some(bounds) => bounds,
+        // An empty sheet makes an empty output sheet
+        None => {
+            let path = write_output(
+                source_file_path,
+                target_folder,
+                sheet_name,
+                |_| Ok(()),
             )?;
+            return Ok(ProcessedFile {
+                path,
+                rows_deleted: options.rows_to_delete(&Range::empty()),
+                rows_written: 0,
+            });
+        }
+    };
+    let rows_to_delete = options.rows_to_delete(&bounds.head);
+    log_detected_rows(options, rows_to_delete, source_file_path);
+    let height = (bounds.end.0 - bounds.start.0) as usize + 1;
+    let width = (bounds.end.1 - bounds.start.1) as usize + 1;
+    check_output_size(height, width, rows_to_delete)?;
+
+    // Second pass: copy the cells below the deleted rows
+    let path =
+        write_output(source_file_path, target_folder, sheet_name, |sheet| {
+            copy_streamed(
+                xlsx,
+                sheet_name,
+                source_file_path,
+                &bounds,
+                rows_to_delete,
+                options,
+                |cell, row_idx, col_idx| sheet.write(cell, row_idx, col_idx),
+            )
+        })?;
+    Ok(ProcessedFile {
+        path,
+        rows_deleted: rows_to_delete,
+        rows_written: height.saturating_sub(rows_to_delete),
+    })
+}
+
+// Reads a sheet for the bounds of its cells and, when banner rows are
+// detected, its first rows. Returns `None` for a sheet without cells.
+fn read_bounds(
+    xlsx: &mut Xlsx<BufReader<File>>,
+    sheet_name: &str,
+    source_file_path: &Path,
+    options: &ProcessOptions,
+) -> Result<Option<SheetBounds>> {
+    let unreadable = unreadable_xlsx(source_file_path);
+    let mut bounds: Option<SheetBounds> = None;
+    let mut head: Vec<Cell<Data>> = Vec::new();
+    let mut cells =
+        xlsx.worksheet_cells_reader(sheet_name).map_err(unreadable)?;
+    while let Some(cell) = cells.next_cell().map_err(unreadable)? {
+        if *cell.get_value() == DataRef::Empty {
+            continue;
+        }
+        let (row, col) = cell.get_position();
+        let bounds = bounds.get_or_insert(SheetBounds {
+            start: (row, col),
+            end: (row, col),
+            head: Range::empty(),
+        });
+        if row != bounds.end.0 {
+            options.cancel.check()?;
+        }
+        bounds.start.1 = bounds.start.1.min(col);
+        bounds.end = (row, bounds.end.1.max(col));
+        if options.detect_header_rows
+            && ((row - bounds.start.0) as usize) < DETECTION_ROWS
+        {
+            head.push(Cell::new((row, col), cell.get_value().clone().into()));
+        }
+    }
+    drop(cells);
+    options.cancel.check()?;
+
+    Ok(bounds.map(|mut bounds| {
+        bounds.head = Range::from_sparse(head);
+        bounds
+    }))
+}
+
+// Streams the cells of a sheet below its first `rows_to_delete` rows to
+// `write`, in row order, with their position in the output. Empty cells are
+// left out.
+fn copy_streamed(
+    xlsx: &mut Xlsx<BufReader<File>>,
+    sheet_name: &str,
+    source_file_path: &Path,
+    bounds: &SheetBounds,
+    rows_to_delete: usize,
+    options: &ProcessOptions,
+    mut write: impl FnMut(&Data, u32, u16) -> Result<()>,
+) -> Result<()> {
+    let unreadable = unreadable_xlsx(source_file_path);
+    let mut cells =
+        xlsx.worksheet_cells_reader(sheet_name).map_err(unreadable)?;
+    let mut current_row = None;
+    while let Some(cell) = cells.next_cell().map_err(unreadable)? {
+        let (row, col) = cell.get_position();
+        if current_row != Some(row) {
+            options.cancel.check()?;
+            current_row = Some(row);
+        }
+        let row_idx = (row - bounds.start.0) as usize;
+        if row_idx < rows_to_delete || *cell.get_value() == DataRef::Empty {
+            continue;
         }
+        write(
+            &cell.get_value().clone().into(),
+            (row_idx - rows_to_delete) as u32,
+            (col - bounds.start.1) as u16,
+        )?;
     }
     Ok(())
 }
 
+// Wraps the errors of reading an .xlsx workbook with its path.
+fn unreadable_xlsx(
+    source_file_path: &Path,
+) -> impl Fn(calamine::XlsxError) -> Error + Copy + '_ {
+    move |source| Error::UnreadableWorkbook {
+        path: source_file_path.to_path_buf(),
+        source: source.into(),
+    }
+}
+
+fn log_detected_rows(
+    options: &ProcessOptions,
+    rows_to_delete: usize,
+    source_file_path: &Path,
+) {
+    if options.detect_header_rows {
+        info!(
+            "Detected {} banner rows in {}",
+            rows_to_delete,
+            source_file_path.display()
+        );
+    }
+}
+
+// Makes sure the rows and columns left after deleting `rows_to_delete` rows
+// of a `height` by `width` sheet fit into an .xlsx worksheet.
+fn check_output_size(
+    height: usize,
+    width: usize,
+    rows_to_delete: usize,
+) -> Result<()> {
+    let output_rows = height.saturating_sub(rows_to_delete);
+    if output_rows > MAX_XLSX_ROWS {
+        return Err(Error::LimitExceeded {
+            what: "Worksheet row".to_string(),
+            limit: MAX_XLSX_ROWS as u64,
+            actual: output_rows as u64,
+        });
+    }
+    if width > MAX_XLSX_COLUMNS {
+        return Err(Error::LimitExceeded {
+            what: "Worksheet column".to_string(),
+            limit: MAX_XLSX_COLUMNS as u64,
+            actual: width as u64,
+        });
+    }
+    Ok(())
+}
+
+// Worksheet of the output workbook, with what is needed to write its cells.
+struct OutputSheet<'a> {
+    sheet: Worksheet<'a>,
+    date_format: &'a Format,
+    target_file_path: &'a Path,
+}
+
+impl OutputSheet<'_> {
+    fn write(&mut self, cell: &Data, row_idx: u32, col_idx: u16) -> Result<()> {
+        process_cell(
+            cell,
+            row_idx,
+            col_idx,
+            &mut self.sheet,
+            self.date_format,
+            self.target_file_path,
+        )
+    }
+}
+
+// Creates the output workbook of `source_file_path` with a single sheet,
+// lets `write_rows` fill it in row order and saves it. Returns its path.
+fn write_output(
+    source_file_path: &Path,
+    target_folder: &Path,
+    sheet_name: &str,
+    write_rows: impl FnOnce(&mut OutputSheet) -> Result<()>,
+) -> Result<PathBuf> {
+    // Generate the path for the processed file
+    let target_file_path =
+        generate_output_file_path(source_file_path, target_folder)?;
+
+    // Create a new Excel workbook for output
+    let workbook_out = create_new_workbook(&target_file_path)?;
+    let write_error = |source| Error::WorkbookWrite {
+        path: target_file_path.clone(),
+        source,
+    };
+
+    // Create a format for date cells
+    let mut date_format = Format::new();
+    date_format.set_num_format("yyyy-mm-dd hh:mm:ss");
+
+    // Add a worksheet to the new workbook
+    let sheet =
+        workbook_out.add_worksheet(Some(sheet_name)).map_err(write_error)?;
+    write_rows(&mut OutputSheet {
+        sheet,
+        date_format: &date_format,
+        target_file_path: &target_file_path,
+    })?;
+    workbook_out.close().map_err(write_error)?;
+    info!("File processed and saved: {}", target_file_path.display());
+    Ok(target_file_path)
+}
+
 /// Processes a single cell from the source Excel data and writes it to the corresponding
 /// position in the output worksheet. The cell type is checked and handled accordingly
 /// (e.g., numbers, dates, strings). For date-like numbers, conversion is applied.
 fn process_cell(
-    cell: &Data,           // The data type of the cell to be processed
-    row_idx: u32,          // Row index in the output worksheet
-    col_idx: u16,          // Column index in the output worksheet
-    sheet: &mut Worksheet, // The worksheet to write the cell to
-    date_format: &Format,  // Date format for formatting date cells
-) -> Result<(), Box<dyn error::Error>> {
+    cell: &Data,             // The data type of the cell to be processed
+    row_idx: u32,            // Row index in the output worksheet
+    col_idx: u16,            // Column index in the output worksheet
+    sheet: &mut Worksheet,   // The worksheet to write the cell to
+    date_format: &Format,    // Date format for formatting date cells
+    target_file_path: &Path, // Path of the workbook being written, for errors
+) -> Result<()> {
+    let write_error = |source| Error::WorkbookWrite {
+        path: target_file_path.to_path_buf(),
+        source,
+    };
+
     match cell {
         Data::Float(f) => {
-            sheet.write_number(row_idx, col_idx, *f, None)?;
+            sheet
+                .write_number(row_idx, col_idx, *f, None)
+                .map_err(write_error)?;
         }
 
         Data::String(s) => {
-            sheet.write_string(row_idx, col_idx, s, None)?;
+            sheet
+                .write_string(row_idx, col_idx, s, None)
+                .map_err(write_error)?;
         }
         Data::Int(i) => {
-            sheet.write_number(row_idx, col_idx, *i as f64, None)?;
+            sheet
+                .write_number(row_idx, col_idx, *i as f64, None)
+                .map_err(write_error)?;
         }
         Data::Bool(b) => {
-            sheet.write_boolean(row_idx, col_idx, *b, None)?;
+            sheet
+                .write_boolean(row_idx, col_idx, *b, None)
+                .map_err(write_error)?;
         }
         Data::DateTime(excel_dt) => {
             let serial_dt: f64 = excel_dt.as_f64();
@@ -167,23 +940,26 @@ fn process_cell(
                     second: naive_dt.second() as f64,
                 };
 
-                sheet.write_datetime(
-                    row_idx,
-                    col_idx,
-                    &xlsx_dt,
-                    Some(&date_format),
-                )?;
+                sheet
+                    .write_datetime(
+                        row_idx,
+                        col_idx,
+                        &xlsx_dt,
+                        Some(date_format),
+                    )
+                    .map_err(write_error)?;
             } else {
                 error!("Error converting datetime to NaiveDateTime: excel_dt => {}, serial_dt => {}", excel_dt, serial_dt);
                 // Handle the case where the conversion fails (optional)
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "Invalid Excel serial date",
-                )));
+                return Err(Error::InvalidDate {
+                    serial: serial_dt,
+                    row: row_idx,
+                    col: col_idx,
+                });
             }
         }
         Data::Error(_) | Data::Empty => {
-            sheet.write_blank(row_idx, col_idx, None)?; // Ignore error or empty cells
+            sheet.write_blank(row_idx, col_idx, None).map_err(write_error)?; // Ignore error or empty cells
         }
         _ => {} // For other data types (e.g., empty cells), nothing is done
     }
@@ -207,3 +983,241 @@ pub fn excel_serial_to_naive_datetime(
         .checked_add_signed(Duration::days(days))?
         .checked_add_signed(Duration::seconds(seconds_in_day))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xlsx_manager::test_workbook::write_xlsx;
+
+    fn file_override(
+        pattern: &str,
+        delete_first_n_rows: Option<usize>,
+        detect_header_rows: Option<bool>,
+    ) -> FileOverride {
+        FileOverride {
+            delete_first_n_rows,
+            detect_header_rows,
+            ..FileOverride::new(pattern).unwrap()
+        }
+    }
+
+    #[test]
+    fn override_matches_file_names_and_globs() {
+        let exact = file_override("legacy.xls", None, None);
+        assert!(exact.matches("legacy.xls"));
+        assert!(!exact.matches("legacy.xlsx"));
+        assert!(!exact.matches("old_legacy.xls"));
+
+        let glob = file_override("sales_*.xlsx", None, None);
+        assert!(glob.matches("sales_jan.xlsx"));
+        assert!(glob.matches("sales_.xlsx"));
+        assert!(!glob.matches("sales_jan.xls"));
+        assert!(!glob.matches("old_sales_jan.xlsx"));
+    }
+
+    #[test]
+    fn override_matches_names_with_glob_characters_literally() {
+        let literal = file_override("report[1].xlsx", None, None);
+        assert!(literal.matches("report[1].xlsx"));
+        // Also read as a glob, where `[1]` is a character class
+        assert!(literal.matches("report1.xlsx"));
+        assert!(!literal.matches("report2.xlsx"));
+    }
+
+    #[test]
+    fn override_matches_the_name_of_a_path() {
+        let glob = file_override("sales_*.xlsx", None, None);
+        assert!(glob.matches("inputs/sales_jan.xlsx"));
+        assert!(glob.matches("/srv/upload/job/inputs/sales_jan.xlsx"));
+        assert!(!glob.matches("sales_jan.xlsx/report.xlsx"));
+
+        let exact = file_override("legacy.xls", None, None);
+        assert!(exact.matches("archive/legacy.xls"));
+        assert!(!exact.matches("legacy.xls/other.xls"));
+
+        let path = file_override("inputs/*.xlsx", None, None);
+        assert!(path.matches("inputs/a.xlsx"));
+        assert!(!path.matches("a.xlsx"));
+        assert!(!path.matches("other/a.xlsx"));
+    }
+
+    #[test]
+    fn invalid_pattern_is_rejected() {
+        let err = FileOverride::new("sales_[.xlsx").unwrap_err();
+        assert!(matches!(err, Error::InvalidPattern { .. }));
+        assert_eq!(err.code(), "invalid_pattern");
+    }
+
+    #[test]
+    fn for_file_applies_the_first_matching_override() {
+        let options = ProcessOptions {
+            delete_first_n_rows: 2,
+            overrides: vec![
+                file_override("sales_*.xlsx", Some(5), None),
+                file_override("*.xlsx", None, Some(true)),
+                file_override("sales_jan.xlsx", Some(9), None),
+            ],
+            ..ProcessOptions::default()
+        };
+
+        let sales = options.for_file("sales_jan.xlsx");
+        assert_eq!(sales.delete_first_n_rows, 5);
+        assert!(!sales.detect_header_rows);
+
+        let other = options.for_file("inputs/other.xlsx");
+        assert_eq!(other.delete_first_n_rows, 2);
+        assert!(other.detect_header_rows);
+
+        let unmatched = options.for_file("sales_jan.xls");
+        assert_eq!(unmatched.delete_first_n_rows, 2);
+        assert!(!unmatched.detect_header_rows);
+    }
+
+    #[test]
+    fn for_file_row_count_turns_off_detection() {
+        let options = ProcessOptions {
+            detect_header_rows: true,
+            overrides: vec![
+                file_override("fixed.xlsx", Some(3), None),
+                file_override("both.xlsx", Some(3), Some(true)),
+            ],
+            ..ProcessOptions::default()
+        };
+
+        let fixed = options.for_file("fixed.xlsx");
+        assert_eq!(fixed.delete_first_n_rows, 3);
+        assert!(!fixed.detect_header_rows);
+
+        // An explicit `detect_header_rows` wins over the row count
+        let both = options.for_file("both.xlsx");
+        assert!(both.detect_header_rows);
+    }
+
+    #[test]
+    fn files_with_the_same_output_name_fail() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let input = dir.join("input");
+        fs::create_dir_all(&input).unwrap();
+        // The names clash before the files are read, so the content does not
+        // matter
+        fs::write(input.join("a.xls"), b"").unwrap();
+        fs::write(input.join("a.xlsx"), b"").unwrap();
+
+        let summary = process_input_folder(
+            &input,
+            &dir.join("extracted"),
+            &dir.join("output"),
+            &ProcessOptions::default(),
+        )
+        .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let duplicate = summary
+            .failed
+            .iter()
+            .find(|failure| failure.file == "a.xlsx")
+            .unwrap();
+        assert_eq!(duplicate.error.code(), "duplicate_output");
+        assert!(summary.failed.iter().any(|failure| failure.file == "a.xls"
+            && failure.error.code() == "unreadable_workbook"));
+    }
+
+    // Cells `process_excel` writes for the first sheet of an .xlsx file when
+    // it is streamed, along with the rows deleted.
+    fn streamed_cells(
+        path: &Path,
+        options: &ProcessOptions,
+    ) -> (usize, Vec<(u32, u16, Data)>) {
+        let mut xlsx: Xlsx<_> = calamine::open_workbook(path).unwrap();
+        let bounds =
+            read_bounds(&mut xlsx, "Sheet1", path, options).unwrap().unwrap();
+        let rows_to_delete = options.rows_to_delete(&bounds.head);
+        let mut cells = Vec::new();
+        copy_streamed(
+            &mut xlsx,
+            "Sheet1",
+            path,
+            &bounds,
+            rows_to_delete,
+            options,
+            |cell, row, col| {
+                cells.push((row, col, cell.clone()));
+                Ok(())
+            },
+        )
+        .unwrap();
+        (rows_to_delete, cells)
+    }
+
+    // Cells written for the same sheet once loaded whole. Empty cells are
+    // written as blanks without a format, which writes nothing.
+    fn loaded_cells(
+        path: &Path,
+        options: &ProcessOptions,
+    ) -> (usize, Vec<(u32, u16, Data)>) {
+        let mut workbook = open_workbook_auto(path).unwrap();
+        let range = workbook.worksheet_range_at(0).unwrap().unwrap();
+        let rows_to_delete = options.rows_to_delete(&range);
+        let mut cells = Vec::new();
+        copy_range(&range, rows_to_delete, |cell, row, col| {
+            if *cell != Data::Empty {
+                cells.push((row, col, cell.clone()));
+            }
+            Ok(())
+        })
+        .unwrap();
+        (rows_to_delete, cells)
+    }
+
+    #[test]
+    fn streamed_sheet_matches_the_loaded_sheet() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.xlsx");
+        let text = |s: &str| Data::String(s.to_string());
+        // Cells start on the second row and column, with gaps in between
+        let mut rows = vec![
+            vec![],
+            vec![Data::Empty, text("Monthly report")],
+            vec![Data::Empty, text("Name"), text("Qty"), text("Paid")],
+        ];
+        for n in 0..20 {
+            rows.push(vec![
+                Data::Empty,
+                if n % 4 == 0 { Data::Empty } else { text(&format!("r{}", n)) },
+                Data::Float(n as f64 * 1.5),
+                if n % 3 == 0 { Data::Empty } else { Data::Bool(n % 2 == 0) },
+            ]);
+        }
+        write_xlsx(&path, &[("Sheet1", rows)]);
+
+        for options in [
+            ProcessOptions::default(),
+            ProcessOptions { delete_first_n_rows: 2, ..Default::default() },
+            ProcessOptions { detect_header_rows: true, ..Default::default() },
+        ] {
+            let streamed = streamed_cells(&path, &options);
+            assert!(!streamed.1.is_empty());
+            assert_eq!(
+                streamed,
+                loaded_cells(&path, &options),
+                "{:?}",
+                options
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn for_file_shares_the_cancel_flag() {
+        let options = ProcessOptions {
+            overrides: vec![file_override("*.xlsx", Some(1), None)],
+            ..ProcessOptions::default()
+        };
+        let file_options = options.for_file("a.xlsx");
+        assert!(file_options.overrides.is_empty());
+
+        options.cancel.cancel();
+        assert!(file_options.cancel.is_cancelled());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::error::{Error, Result};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+use walkdir::WalkDir;
+use zip::result::ZipError;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+/// Bounds on what extracting a ZIP file may write, guarding against archives
+/// that expand to far more than their size. `0` means no limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZipLimits {
+    /// Total uncompressed bytes of the entries
+    pub max_extracted_size: u64,
+    /// Number of entries, folders included
+    pub max_entries: usize,
+}
+
+/// Extracts the ZIP file next to itself and removes the archive afterwards.
+pub fn extract_zip_file(zip_path: &Path, limits: &ZipLimits) -> Result<()> {
+    // Get the output directory based on the ZIP file's location
+    let output_directory = zip_path.parent().ok_or_else(|| {
+        Error::InvalidFilePath(format!(
+            "Failed to get parent directory of {:?}",
+            zip_path
+        ))
+    })?;
+
+    extract_zip_file_to(zip_path, output_directory, limits)?;
+
+    // Remove the ZIP file after extraction
+    fs::remove_file(zip_path).map_err(Error::io(zip_path))?;
+
+    Ok(())
+}
+
+/// Extracts every entry of the ZIP file into `output_directory`, leaving the
+/// archive itself untouched. Fails with [`Error::LimitExceeded`] as soon as
+/// the archive goes over `limits`; the entries written until then are left
+/// in `output_directory`.
+pub fn extract_zip_file_to(
+    zip_path: &Path,
+    output_directory: &Path,
+    limits: &ZipLimits,
+) -> Result<()> {
+    let read_error =
+        |source| Error::ZipRead { path: zip_path.to_path_buf(), source };
+    let zip_file = File::open(zip_path).map_err(Error::io(zip_path))?;
+    let mut archive = ZipArchive::new(zip_file).map_err(read_error)?;
+    if limits.max_entries > 0 && archive.len() > limits.max_entries {
+        return Err(Error::LimitExceeded {
+            what: "Zip entry count".to_string(),
+            limit: limits.max_entries as u64,
+            actual: archive.len() as u64,
+        });
+    }
+    let max_size = match limits.max_extracted_size {
+        0 => u64::MAX,
+        limit => limit,
+    };
+    let size_exceeded = |actual| Error::LimitExceeded {
+        what: "Extracted size".to_string(),
+        limit: max_size,
+        actual,
+    };
+
+    // Unzip files. Entry headers may understate their size, so the bytes
+    // actually written are counted too.
+    let mut extracted: u64 = 0;
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index).map_err(read_error)?;
+        // Reject entries such as `../x` or `/x` that would be written outside
+        // of the output directory
+        let entry_path = file.enclosed_name().ok_or_else(|| {
+            read_error(ZipError::InvalidArchive(
+                "Entry path escapes the extraction folder",
+            ))
+        })?;
+        let output_file_path = output_directory.join(entry_path);
+
+        if file.is_dir() {
+            fs::create_dir_all(&output_file_path)
+                .map_err(Error::io(&output_file_path))?;
+            continue;
+        }
+        if let Some(parent) = output_file_path.parent() {
+            fs::create_dir_all(parent).map_err(Error::io(parent))?;
+        }
+
+        let declared = extracted.saturating_add(file.size());
+        if declared > max_size {
+            return Err(size_exceeded(declared));
+        }
+
+        let mut output_file = File::create(&output_file_path)
+            .map_err(Error::io(&output_file_path))?;
+        let remaining = max_size - extracted;
+        let written = io::copy(
+            &mut (&mut file).take(remaining.saturating_add(1)),
+            &mut output_file,
+        )
+        .map_err(Error::io(&output_file_path))?;
+        extracted += written;
+        if extracted > max_size {
+            return Err(size_exceeded(extracted));
+        }
+    }
+
+    Ok(())
+}
+
+/// Compresses `folder_path` into a ZIP file named after the folder and
+/// returns the path of the new ZIP file.
+pub fn create_zip_from_folder(folder_path: &Path) -> Result<PathBuf> {
+    // Create the ZIP file path
+    let zip_file_path = folder_path.with_extension("zip");
+    create_zip_at(folder_path, &zip_file_path)?;
+    Ok(zip_file_path)
+}
+
+/// Compresses the contents of `folder_path` into a ZIP file written to
+/// `zip_file_path`.
+pub fn create_zip_at(folder_path: &Path, zip_file_path: &Path) -> Result<()> {
+    // Check if the folder exists
+    if !folder_path.is_dir() {
+        return Err(Error::InvalidFilePath(format!(
+            "The path {:?} is not a directory.",
+            folder_path
+        )));
+    }
+
+    let write_error =
+        |source| Error::ZipWrite { path: zip_file_path.to_path_buf(), source };
+
+    // Create a new ZIP file
+    let zip_file =
+        fs::File::create(zip_file_path).map_err(Error::io(zip_file_path))?;
+    let mut zip_writer = ZipWriter::new(zip_file);
+
+    for entry in WalkDir::new(folder_path) {
+        let entry = entry.map_err(|e| Error::Io {
+            path: e.path().unwrap_or(folder_path).to_path_buf(),
+            source: e.into(),
+        })?;
+        let entry_path = entry.path();
+
+        // Get the relative path from the base folder
+        let relative_path = entry_path
+            .strip_prefix(folder_path)
+            .map_err(|e| Error::InvalidFilePath(e.to_string()))?;
+        let relative_path_str = relative_path.to_string_lossy();
+
+        if entry_path.is_dir() {
+            // Use () as the FileOptionExtension for directories
+            zip_writer
+                .add_directory::<_, ()>(
+                    relative_path_str.as_ref(),
+                    FileOptions::default(),
+                )
+                .map_err(write_error)?;
+        } else {
+            // Add a file to the ZIP, specify the type explicitly
+            let options: FileOptions<'_, ()> = FileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .large_file(false);
+
+            zip_writer
+                .start_file(relative_path_str.as_ref(), options)
+                .map_err(write_error)?;
+
+            let mut file =
+                fs::File::open(entry_path).map_err(Error::io(entry_path))?;
+            io::copy(&mut file, &mut zip_writer)
+                .map_err(Error::io(zip_file_path))?;
+        }
+    }
+
+    zip_writer.finish().map_err(write_error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use uuid::Uuid;
+
+    // Writes a ZIP file holding `entries` files of `size` zero bytes each.
+    fn write_zip(path: &Path, entries: usize, size: usize) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        let options: FileOptions<'_, ()> = FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        for index in 0..entries {
+            zip.start_file(format!("{}.xlsx", index), options).unwrap();
+            zip.write_all(&vec![0; size]).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn limit_exceeded(result: Result<()>) -> (String, u64) {
+        match result {
+            Err(Error::LimitExceeded { what, actual, .. }) => (what, actual),
+            other => panic!("expected a limit error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn extraction_stops_at_the_size_limit() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        let zip_path = dir.join("bomb.zip");
+        write_zip(&zip_path, 3, 1024 * 1024);
+
+        let limits = ZipLimits {
+            max_extracted_size: 2 * 1024 * 1024 + 1,
+            max_entries: 0,
+        };
+        let (what, actual) = limit_exceeded(extract_zip_file_to(
+            &zip_path,
+            &dir.join("out"),
+            &limits,
+        ));
+        assert_eq!(what, "Extracted size");
+        assert_eq!(actual, 3 * 1024 * 1024);
+
+        let limits =
+            ZipLimits { max_extracted_size: 3 * 1024 * 1024, max_entries: 0 };
+        extract_zip_file_to(&zip_path, &dir.join("ok"), &limits).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn extraction_rejects_too_many_entries() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        fs::create_dir_all(&dir).unwrap();
+        let zip_path = dir.join("many.zip");
+        write_zip(&zip_path, 5, 1);
+
+        let limits = ZipLimits { max_extracted_size: 0, max_entries: 4 };
+        let (what, actual) = limit_exceeded(extract_zip_file_to(
+            &zip_path,
+            &dir.join("out"),
+            &limits,
+        ));
+        assert_eq!(what, "Zip entry count");
+        assert_eq!(actual, 5);
+        assert!(!dir.join("out").exists());
+
+        let limits = ZipLimits { max_extracted_size: 0, max_entries: 5 };
+        extract_zip_file_to(&zip_path, &dir.join("ok"), &limits).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
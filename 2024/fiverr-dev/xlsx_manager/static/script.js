@@ -22,6 +22,12 @@ document.addEventListener("DOMContentLoaded", () => {
     folderInput.webkitdirectory = true; // Allows folder selection
     folderInput.style.display = "none";
 
+    // Headers of every API call, with the API key when one is entered
+    const apiHeaders = (headers = {}) => {
+        const apiKey = document.getElementById("apiKey").value.trim();
+        return apiKey ? { ...headers, "X-API-Key": apiKey } : headers;
+    };
+
     document.body.appendChild(excelInput);
     document.body.appendChild(zipInput);
     document.body.appendChild(folderInput);
@@ -121,9 +127,14 @@ document.addEventListener("DOMContentLoaded", () => {
             const uploadStartTime = Date.now(); // Record start time for upload
             const uploadResponse = await fetch("http://localhost:8080/upload", {
                 method: "POST",
+                headers: apiHeaders(),
                 body: formData,
             });
 
+            if (!uploadResponse.ok) {
+                throw await apiError(uploadResponse);
+            }
+
             const jobId = await uploadResponse.text();
             job_id = jobId;
 
@@ -136,9 +147,9 @@ document.addEventListener("DOMContentLoaded", () => {
             const processStartTime = Date.now(); // Record start time for processing
             const processResponse = await fetch("http://localhost:8080/process", {
                 method: "POST",
-                headers: {
+                headers: apiHeaders({
                     "Content-Type": "application/json",
-                },
+                }),
                 body: JSON.stringify({
                     job_id: job_id,
                     num_rows_to_delete: parseInt(deleteRows),
@@ -147,6 +158,10 @@ document.addEventListener("DOMContentLoaded", () => {
 
             console.log("Process response:", processResponse);
 
+            if (!processResponse.ok) {
+                throw await apiError(processResponse);
+            }
+
             const processData = await processResponse.json();
 
             console.log("(POST)Process Response: ", processData);
@@ -158,9 +173,9 @@ document.addEventListener("DOMContentLoaded", () => {
             // Third API call: Download
             const downloadResponse = await fetch("http://localhost:8080/download", {
                 method: "POST",
-                headers: {
+                headers: apiHeaders({
                     "Content-Type": "application/json",
-                },
+                }),
                 body: JSON.stringify({
                     file_url: processData.zip_file_name,
                 }),
@@ -185,7 +200,7 @@ document.addEventListener("DOMContentLoaded", () => {
 
         } catch (error) {
             console.error("Error:", error);
-            updateStatus("Failed to process files. Please try again.", true, "red");
+            updateStatus(`Failed to process files: ${error.message}`, true, "red");
         }
     });
 
@@ -206,9 +221,9 @@ document.addEventListener("DOMContentLoaded", () => {
         try {
             const removeResponse = await fetch(`http://localhost:8080/remove/${job_id}`, {
                 method: "DELETE",
-                headers: {
+                headers: apiHeaders({
                     "Content-Type": "application/json",
-                },
+                }),
             });
 
             console.log("(DELETE) Remove Response: ", removeResponse);
@@ -222,6 +237,16 @@ document.addEventListener("DOMContentLoaded", () => {
         }
     });
 
+    // Build an Error from the JSON error envelope ({code, message, job_id, details})
+    async function apiError(response) {
+        try {
+            const body = await response.json();
+            return new Error(`${body.message} (${body.code})`);
+        } catch (e) {
+            return new Error(response.statusText);
+        }
+    }
+
     // Function to update the status message
     function updateStatus(message, show = true, color = "black") {
         const statusContainer = document.getElementById("statusContainer");